@@ -0,0 +1,145 @@
+//! This module implements the **allowlist** behaviour, which restricts connections to
+//! listed peers once it is enabled.
+//!
+//! The behaviour is always part of the swarm, so the allowlist can be turned on from
+//! the command line even if the node started without one. While disabled it accepts
+//! any peer; once enabled it refuses unlisted peers in both directions, and closes
+//! the connections of a peer removed from the list.
+
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+use libp2p::{
+    allow_block_list,
+    core::{transport::PortUse, Endpoint},
+    swarm::{
+        dummy, ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+        THandlerOutEvent, ToSwarm,
+    },
+    Multiaddr, PeerId,
+};
+
+/// The peers allowed to connect, enforced only while the allowlist is enabled.
+#[derive(Default)]
+pub struct Allowlist {
+    /// Whether unlisted peers are refused.
+    enabled: bool,
+
+    /// The listed peers, which also closes the connections of removed peers.
+    peers: allow_block_list::Behaviour<allow_block_list::AllowedPeers>,
+}
+
+impl Allowlist {
+    /// Creates an allowlist of the given peers, enabled if `peers` is `Some`.
+    ///
+    /// # Arguments
+    ///
+    /// * `peers` - The only peers allowed to connect, or `None` to accept any peer.
+    pub fn new(peers: Option<Vec<PeerId>>) -> Self {
+        let mut allowlist = Allowlist::default();
+        if let Some(peers) = peers {
+            allowlist.enabled = true;
+            for peer_id in peers {
+                allowlist.peers.allow_peer(peer_id);
+            }
+        }
+        allowlist
+    }
+
+    /// Returns whether unlisted peers are refused.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns whether a peer may connect: any peer while the allowlist is disabled,
+    /// otherwise only the listed ones.
+    pub fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        !self.enabled || self.peers.allowed_peers().contains(peer_id)
+    }
+
+    /// Returns the listed peers.
+    pub fn allowed_peers(&self) -> &HashSet<PeerId> {
+        self.peers.allowed_peers()
+    }
+
+    /// Lists a peer and enables the allowlist.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the peer was added.
+    /// - `false` if it was already listed.
+    pub fn allow_peer(&mut self, peer_id: PeerId) -> bool {
+        self.enabled = true;
+        self.peers.allow_peer(peer_id)
+    }
+
+    /// Removes a peer from the list and closes its connections if the allowlist is enabled.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the peer was removed.
+    /// - `false` if it was not listed.
+    pub fn disallow_peer(&mut self, peer_id: PeerId) -> bool {
+        self.peers.disallow_peer(peer_id)
+    }
+}
+
+impl NetworkBehaviour for Allowlist {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        local_addr: &Multiaddr,
+        remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if !self.enabled {
+            return Ok(dummy::ConnectionHandler);
+        }
+        self.peers.handle_established_inbound_connection(connection_id, peer, local_addr, remote_addr)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        maybe_peer: Option<PeerId>,
+        addresses: &[Multiaddr],
+        effective_role: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if !self.enabled {
+            return Ok(Vec::new());
+        }
+        self.peers.handle_pending_outbound_connection(connection_id, maybe_peer, addresses, effective_role)
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        addr: &Multiaddr,
+        role_override: Endpoint,
+        port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        if !self.enabled {
+            return Ok(dummy::ConnectionHandler);
+        }
+        self.peers.handle_established_outbound_connection(connection_id, peer, addr, role_override, port_use)
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        self.peers.on_swarm_event(event);
+    }
+
+    fn on_connection_handler_event(&mut self, _peer_id: PeerId, _connection_id: ConnectionId, event: THandlerOutEvent<Self>) {
+        match event {}
+    }
+
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        self.peers.poll(cx)
+    }
+}
@@ -2,10 +2,17 @@
 //!
 //! It provides methods for creating new blocks, generating the genesis block, 
 //! calculating block hashes, and serializing/deserializing blocks.
+//!
+//! It also defines **compact blocks**, which replace the transactions of a block
+//! with short ids so a peer can rebuild the block from its own mempool.
 
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::HashMap;
 use sha2::{Sha256, Digest};
 use serde::{Serialize, Deserialize};  // Import serialization traits
+use libp2p::identity;
+
+use crate::crypto;
+use crate::transaction::Transaction;
 
 /// Represents a single block in the blockchain.
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -21,6 +28,112 @@ pub struct Block {
 
     /// The height (index) of the block in the blockchain.
     pub height: usize,
+
+    /// The hex-encoded Ed25519 public key of the node that produced the block.
+    #[serde(default)]
+    pub producer: Option<String>,
+
+    /// The producer's hex-encoded signature over the block hash.
+    #[serde(default)]
+    pub signature: Option<String>,
+
+    /// The transactions included in the block.
+    #[serde(default)]
+    pub transactions: Vec<Transaction>,
+}
+
+/// The header of a block: everything but its transactions.
+///
+/// Headers let a node learn the shape of a peer's chain before downloading block bodies.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BlockHeader {
+    /// The timestamp of when the block was created (milliseconds since UNIX epoch).
+    pub timestamp: u128,
+
+    /// The hash of the previous block in the blockchain.
+    pub prev_block_hash: String,
+
+    /// The unique hash of the block.
+    pub hash: String,
+
+    /// The height (index) of the block in the blockchain.
+    pub height: usize,
+
+    /// The hex-encoded Ed25519 public key of the node that produced the block.
+    pub producer: Option<String>,
+
+    /// The producer's hex-encoded signature over the block hash.
+    pub signature: Option<String>,
+}
+
+/// A block whose transactions are replaced by short ids.
+///
+/// The receiver matches the short ids against its mempool and only needs to
+/// download the transactions it does not hold.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CompactBlock {
+    /// The header of the block.
+    pub header: BlockHeader,
+
+    /// The short id of each transaction of the block, in order (see [`short_tx_id`]).
+    pub short_ids: Vec<u64>,
+}
+
+impl CompactBlock {
+    /// Matches the short ids against known transactions.
+    ///
+    /// # Arguments
+    ///
+    /// * `known` - The transactions the node holds, e.g. its mempool.
+    ///
+    /// # Returns
+    ///
+    /// The transaction of each short id, in block order, or `None` where it is missing.
+    pub fn match_transactions(&self, known: &[Transaction]) -> Vec<Option<Transaction>> {
+        let by_short_id: HashMap<u64, &Transaction> = known.iter()
+            .map(|tx| (short_tx_id(&self.header.hash, tx), tx))
+            .collect();
+        self.short_ids.iter()
+            .map(|short_id| by_short_id.get(short_id).map(|tx| (*tx).clone()))
+            .collect()
+    }
+
+    /// Rebuilds the full block from its transactions.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(Block)` if the transactions hash to the header's hash.
+    /// - `None` if a transaction is wrong, e.g. because of a short id collision.
+    pub fn into_block(self, transactions: Vec<Transaction>) -> Option<Block> {
+        let header = self.header;
+        let block = Block {
+            timestamp: header.timestamp,
+            prev_block_hash: header.prev_block_hash,
+            hash: header.hash,
+            height: header.height,
+            producer: header.producer,
+            signature: header.signature,
+            transactions,
+        };
+        (block.calculate_hash() == block.hash).then_some(block)
+    }
+}
+
+/// Computes the short id of a transaction within a block: the first 6 bytes of
+/// `SHA-256(block hash || transaction id)`.
+///
+/// Salting with the block hash keeps short id collisions from being prepared in advance.
+///
+/// # Arguments
+///
+/// * `block_hash` - The hash of the block including the transaction.
+/// * `tx` - The transaction.
+pub fn short_tx_id(block_hash: &str, tx: &Transaction) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(block_hash)
+        .chain_update(tx.id())
+        .finalize();
+    digest[..6].iter().fold(0, |short_id, byte| short_id << 8 | u64::from(*byte))
 }
 
 impl Block {
@@ -30,6 +143,8 @@ impl Block {
     ///
     /// * `prev_block_hash` - The hash of the previous block.
     /// * `height` - The position of the block in the blockchain.
+    /// * `timestamp` - The creation time of the block, usually [`crate::blockchain::Blockchain::next_timestamp`].
+    /// * `transactions` - The transactions included in the block.
     ///
     /// # Returns
     ///
@@ -39,27 +154,30 @@ impl Block {
     ///
     /// ```rust
     /// let prev_hash = "abc123".to_string();
-    /// let block = Block::new_block(prev_hash, 1);
+    /// let block = Block::new_block(prev_hash, 1, 1_700_000_000_000, Vec::new());
     /// ```
-    pub fn new_block(prev_block_hash: String, height: usize) -> Block {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis();  
-
-        let hash = Self::calculate_hash(timestamp, &prev_block_hash);
-
-        Block {
+    pub fn new_block(prev_block_hash: String, height: usize, timestamp: u128, transactions: Vec<Transaction>) -> Block {
+        let mut block = Block {
             timestamp,
             prev_block_hash,
-            hash,
+            hash: String::new(),
             height,
-        }
+            producer: None,
+            signature: None,
+            transactions,
+        };
+        block.hash = block.calculate_hash();
+        block
     }
 
     /// Generates the **Genesis Block**, the first block in the blockchain.
     ///
     /// The genesis block has a height of `0` and a predefined previous hash (`64` zeros).
+    /// Its timestamp comes from the chain spec, so all nodes of a chain share the same genesis hash.
+    ///
+    /// # Arguments
+    ///
+    /// * `timestamp` - The genesis timestamp defined by the chain spec.
     ///
     /// # Returns
     ///
@@ -68,32 +186,29 @@ impl Block {
     /// # Example
     ///
     /// ```rust
-    /// let genesis = Block::genesis_block();
+    /// let genesis = Block::genesis_block(0);
     /// assert_eq!(genesis.height, 0);
     /// ```
-    pub fn genesis_block() -> Block {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_millis();
-
+    pub fn genesis_block(timestamp: u128) -> Block {
         let prev_block_hash = "0".repeat(64);  // Default hash for genesis block
-        let hash = Self::calculate_hash(timestamp, &prev_block_hash);
 
-        Block {
+        let mut block = Block {
             timestamp,
             prev_block_hash,
-            hash,
+            hash: String::new(),
             height: 0,  // Genesis block always starts at height 0
-        }
+            producer: None,
+            signature: None,
+            transactions: Vec::new(),
+        };
+        block.hash = block.calculate_hash();
+        block
     }
 
-    /// Computes the SHA-256 hash of the block based on its timestamp and previous hash.
+    /// Computes the SHA-256 hash of the block header.
     ///
-    /// # Arguments
-    ///
-    /// * `timestamp` - The block's creation timestamp.
-    /// * `prev_block_hash` - The hash of the previous block.
+    /// The hash covers the timestamp, the previous hash, the height, the producer
+    /// and the transaction ids, so a producer's signature over the hash commits to the whole block.
     ///
     /// # Returns
     ///
@@ -102,50 +217,78 @@ impl Block {
     /// # Example
     ///
     /// ```rust
-    /// let hash = Block::calculate_hash(1234567890, "previous_hash");
+    /// let block = Block::genesis_block(0);
+    /// assert_eq!(block.calculate_hash(), block.get_hash());
     /// ```
-    pub fn calculate_hash(timestamp: u128, prev_block_hash: &str) -> String {
+    pub fn calculate_hash(&self) -> String {
         let mut hasher = Sha256::new();
-        hasher.update(timestamp.to_string());
-        hasher.update(prev_block_hash);  // Include previous block's hash in hashing
+        hasher.update(self.timestamp.to_string());
+        hasher.update(&self.prev_block_hash);  // Include previous block's hash in hashing
+        hasher.update(self.height.to_string());
+        if let Some(producer) = &self.producer {
+            hasher.update(producer);
+        }
+        for tx in &self.transactions {
+            hasher.update(tx.id());
+        }
         let result = hasher.finalize();
         format!("{:x}", result) // Convert hash bytes to hexadecimal string
     }
 
-    /// Serializes the block into a JSON string.
+    /// Signs the block with the producer's keypair.
     ///
-    /// # Returns
+    /// Sets the producer field to the keypair's public key, recomputes the hash
+    /// and stores the signature over it.
     ///
-    /// A `String` containing the serialized JSON representation of the block.
+    /// # Arguments
+    ///
+    /// * `keypair` - The Ed25519 keypair of the producing node.
     ///
     /// # Example
     ///
     /// ```rust
-    /// let block = Block::genesis_block();
-    /// let json = block.serialize();
+    /// let keypair = identity::Keypair::generate_ed25519();
+    /// let mut block = Block::new_block("abc123".to_string(), 1, 1_700_000_000_000, Vec::new());
+    /// block.sign(&keypair);
+    /// assert!(block.verify_signature());
     /// ```
-    pub fn serialize(&self) -> String {
-        serde_json::to_string(self).expect("Failed to serialize block")
+    pub fn sign(&mut self, keypair: &identity::Keypair) {
+        self.producer = crypto::encode_public_key(keypair);
+        self.hash = self.calculate_hash();
+        self.signature = crypto::sign(keypair, self.hash.as_bytes());
     }
 
-    /// Deserializes a JSON string into a `Block` instance.
-    ///
-    /// # Arguments
-    ///
-    /// * `json_data` - The JSON string representing a block.
+    /// Verifies the producer's signature over the block hash.
     ///
     /// # Returns
     ///
-    /// A `Block` instance parsed from the JSON data.
-    ///
-    /// # Example
-    ///
-    /// ```rust
-    /// let json = r#"{"timestamp": 123456, "prev_block_hash": "0", "hash": "xyz", "height": 0}"#;
-    /// let block = Block::deserialize(json);
-    /// ```
-    pub fn deserialize(json_data: &str) -> Block {
-        serde_json::from_str(json_data).expect("Failed to deserialize block")
+    /// - `true` if the block carries a producer and a valid signature from it.
+    /// - `false` if the block is unsigned or the signature does not match.
+    pub fn verify_signature(&self) -> bool {
+        match (&self.producer, &self.signature) {
+            (Some(producer), Some(signature)) => crypto::verify(producer, self.hash.as_bytes(), signature),
+            _ => false,
+        }
+    }
+
+    /// Returns the header of the block.
+    pub fn header(&self) -> BlockHeader {
+        BlockHeader {
+            timestamp: self.timestamp,
+            prev_block_hash: self.prev_block_hash.clone(),
+            hash: self.hash.clone(),
+            height: self.height,
+            producer: self.producer.clone(),
+            signature: self.signature.clone(),
+        }
+    }
+
+    /// Returns the compact form of the block: its header and the short ids of its transactions.
+    pub fn compact(&self) -> CompactBlock {
+        CompactBlock {
+            header: self.header(),
+            short_ids: self.transactions.iter().map(|tx| short_tx_id(&self.hash, tx)).collect(),
+        }
     }
 
     /// Returns the hash of the block.
@@ -1,9 +1,25 @@
 //! This module defines the **Blockchain** structure and its associated methods.
 //!
 //! The `Blockchain` struct manages a chain of blocks, ensuring data integrity
-//! and validating blocks before adding them to the chain.
+//! and validating blocks against the chain spec before adding them to the chain.
+//!
+//! Block timestamps must not be earlier than the median timestamp of the previous
+//! [`MEDIAN_TIME_SPAN`] blocks, nor later than [`MAX_FUTURE_DRIFT`] ahead of the
+//! network-adjusted time (see [`crate::clock`]).
+
+use std::time::Duration;
 
 use crate::block::Block;  // Import the Block struct
+use crate::chain_spec::ChainSpec;
+use crate::clock;
+use crate::consensus;
+use crate::state::ChainState;
+
+/// Number of previous blocks whose median timestamp a new block may not be earlier than.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far ahead of the network-adjusted time a block timestamp may be.
+const MAX_FUTURE_DRIFT: Duration = Duration::from_secs(60);
 
 /// Represents a blockchain, which consists of a sequence of blocks.
 ///
@@ -13,60 +29,52 @@ use crate::block::Block;  // Import the Block struct
 pub struct Blockchain {
     /// The list of blocks in the blockchain.
     blocks: Vec<Block>,
+
+    /// The chain spec defining the genesis block and the consensus rules.
+    spec: ChainSpec,
+
+    /// The chain state after applying every block.
+    state: ChainState,
+
+    /// Height of the last block finalized by validator votes.
+    finalized_height: usize,
+
+    /// Offset of the network-adjusted time from the local clock, in milliseconds.
+    time_offset: i64,
 }
 
 impl Blockchain {
-    /// Initializes a new blockchain with a **genesis block**.
-    ///
-    /// # Returns
-    ///
-    /// A `Blockchain` instance with a single genesis block.
-    ///
-    /// # Example
-    ///
-    /// ```rust
-    /// let blockchain = Blockchain::new();
-    /// ```
-    pub fn new() -> Self {
-        let genesis_block = Block::genesis_block();
-        Blockchain {
-            blocks: vec![genesis_block],
-        }
-    }
-
-    /// Creates a blockchain from an existing list of blocks.
-    ///
-    /// This method ensures that the provided blocks form a valid blockchain.
+    /// Initializes a new blockchain with the **genesis block** of the given chain spec.
     ///
     /// # Arguments
     ///
-    /// * `data` - A vector of `Block` instances representing an existing blockchain.
-    ///
-    /// # Panics
-    ///
-    /// If the provided blockchain is invalid, the function **panics** to prevent corruption.
+    /// * `spec` - The chain spec the blockchain follows.
     ///
     /// # Returns
     ///
-    /// A `Blockchain` instance built from the given blocks.
+    /// A `Blockchain` instance with a single genesis block.
     ///
     /// # Example
     ///
     /// ```rust
-    /// let blocks = vec![Block::genesis_block()];
-    /// let blockchain = Blockchain::from_blocks(blocks);
+    /// let blockchain = Blockchain::new(ChainSpec::default());
     /// ```
-    pub fn from_blocks(data: Vec<Block>) -> Self {
-        let blockchain = Blockchain { blocks: data };
-        if !blockchain.is_valid() {
-            panic!("Invalid blockchain provided!");
+    pub fn new(spec: ChainSpec) -> Self {
+        let genesis_block = Block::genesis_block(spec.genesis_timestamp);
+        Blockchain {
+            blocks: vec![genesis_block],
+            state: ChainState::genesis(&spec),
+            spec,
+            finalized_height: 0,
+            time_offset: 0,
         }
-        blockchain
     }
 
     /// Adds a new block to the blockchain after validating its integrity.
     ///
-    /// The new block must have a **previous hash** that matches the last block’s hash.
+    /// The new block must have a **previous hash** that matches the last block’s hash,
+    /// satisfy the consensus rules of the chain spec and only contain transactions
+    /// that apply cleanly to the chain state.
     ///
     /// # Arguments
     ///
@@ -75,33 +83,30 @@ impl Blockchain {
     /// # Returns
     ///
     /// - `true` if the block was successfully added.
-    /// - `false` if the block was rejected due to **an invalid previous hash**.
+    /// - `false` if the block was rejected (e.g. **an invalid previous hash** or a producer out of turn).
     ///
     /// # Example
     ///
     /// ```rust
-    /// let mut blockchain = Blockchain::new();
+    /// let mut blockchain = Blockchain::new(ChainSpec::default());
     /// let prev_block = blockchain.get_last_block().unwrap();
-    /// let new_block = Block::new_block(prev_block.get_hash(), prev_block.get_height() + 1);
+    /// let new_block = Block::new_block(prev_block.get_hash(), prev_block.get_height() + 1, blockchain.next_timestamp(), Vec::new());
     /// let added = blockchain.add_block(new_block);
     /// assert!(added);
     /// ```
     pub fn add_block(&mut self, block: Block) -> bool {
-        // Get the last block for validation
-        if let Some(last_block) = self.get_last_block() {
-            // Check if the block’s previous hash matches the last block’s hash
-            if block.get_prev_hash() != last_block.get_hash() {
-                println!("Block rejected: Invalid previous hash.");
-                return false;
+        match self.validate_block(&block) {
+            Ok(state) => {
+                // If validation passes, add the block
+                self.state = state;
+                self.blocks.push(block);
+                println!("Block successfully added.");
+                true
+            }
+            Err(reason) => {
+                println!("Block rejected: {}", reason);
+                false
             }
-
-            // If validation passes, add the block
-            self.blocks.push(block);
-            println!("Block successfully added.");
-            true
-        } else {
-            println!("Blockchain is empty. Cannot add block.");
-            false
         }
     }
     
@@ -114,7 +119,7 @@ impl Blockchain {
     /// # Example
     ///
     /// ```rust
-    /// let blockchain = Blockchain::new();
+    /// let blockchain = Blockchain::new(ChainSpec::default());
     /// let blocks = blockchain.get_blocks();
     /// assert_eq!(blocks.len(), 1); // Should contain the genesis block.
     /// ```
@@ -132,48 +137,266 @@ impl Blockchain {
     /// # Example
     ///
     /// ```rust
-    /// let blockchain = Blockchain::new();
+    /// let blockchain = Blockchain::new(ChainSpec::default());
     /// let last_block = blockchain.get_last_block().unwrap();
     /// ```
     pub fn get_last_block(&self) -> Option<&Block> {
         self.blocks.last()
     }    
 
-    /// Validates the blockchain to ensure its integrity.
+    /// Returns the chain spec the blockchain follows.
+    pub fn get_spec(&self) -> &ChainSpec {
+        &self.spec
+    }
+
+    /// Returns the chain state after the last block.
+    pub fn get_state(&self) -> &ChainState {
+        &self.state
+    }
+
+    /// Returns the height of the last finalized block.
     ///
-    /// This method checks:
-    /// - That each block’s `prev_block_hash` matches the hash of the previous block.
-    /// - That each block’s hash is correctly computed.
+    /// Blocks up to this height are irreversible: the blockchain refuses any
+    /// reorganization that would replace them. The genesis block is always final.
+    pub fn finalized_height(&self) -> usize {
+        self.finalized_height
+    }
+
+    /// Marks the block at `height` and all blocks below it as final.
     ///
     /// # Returns
     ///
-    /// - `true` if the blockchain is valid.
-    /// - `false` if **any block is tampered with or out of order**.
+    /// - `true` if the finalized height advanced.
+    /// - `false` if the block is unknown or already final.
+    pub fn finalize(&mut self, height: usize) -> bool {
+        if height <= self.finalized_height || height >= self.blocks.len() {
+            return false;
+        }
+        self.finalized_height = height;
+        true
+    }
+
+    /// Replaces the local blockchain with a competing chain received from a peer.
     ///
-    /// # Example
+    /// Fork choice follows the longest valid chain, but never reorganizes below
+    /// the last finalized block.
     ///
-    /// ```rust
-    /// let blockchain = Blockchain::new();
-    /// assert!(blockchain.is_valid());
-    /// ```
-    pub fn is_valid(&self) -> bool {
-        for i in 1..self.blocks.len() {
-            let current = &self.blocks[i];
-            let previous = &self.blocks[i - 1];
-
-            // Check that the previous hash matches
-            if current.get_prev_hash() != previous.get_hash() {
-                println!("Block {} has an invalid previous hash!", i);
-                return false;
-            }
+    /// # Arguments
+    ///
+    /// * `blocks` - The competing chain, starting at genesis.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the local blockchain was replaced.
+    /// - `false` if the competing chain is invalid, not longer, or conflicts with a finalized block.
+    pub fn replace_chain(&mut self, blocks: Vec<Block>) -> bool {
+        if blocks.len() <= self.blocks.len() {
+            return false;
+        }
+
+        let finalized_hash = self.blocks[self.finalized_height].get_hash();
+        if blocks.get(self.finalized_height).map(|b| b.get_hash()) != Some(finalized_hash) {
+            println!("Chain rejected: it reorganizes below the finalized height {}.", self.finalized_height);
+            return false;
+        }
 
-            // Recalculate hash and compare it to the stored hash
-            let recalculated_hash = Block::calculate_hash(current.timestamp, &current.prev_block_hash);
-            if current.get_hash() != recalculated_hash {
-                println!("Block {} has been tampered with!", i);
-                return false;
+        match Self::replay(blocks, self.spec.clone(), self.time_offset) {
+            Ok(replayed) => {
+                self.blocks = replayed.blocks;
+                self.state = replayed.state;
+                println!("Local blockchain replaced by a longer chain.");
+                true
+            }
+            Err(reason) => {
+                println!("Chain rejected: {}", reason);
+                false
             }
         }
-        true
+    }
+
+    /// Sets the offset of the network-adjusted time from the local clock.
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset in milliseconds, see [`clock::NetworkClock::offset`].
+    pub fn set_time_offset(&mut self, offset: i64) {
+        self.time_offset = offset;
+    }
+
+    /// Returns the network-adjusted time, in milliseconds since the UNIX epoch.
+    pub fn network_time(&self) -> u128 {
+        (clock::local_time() as i64 + self.time_offset) as u128
+    }
+
+    /// Returns the median timestamp of the last [`MEDIAN_TIME_SPAN`] blocks, which the
+    /// next block may not be earlier than.
+    pub fn median_time_past(&self) -> u128 {
+        let mut timestamps: Vec<u128> = self.blocks.iter()
+            .rev()
+            .take(MEDIAN_TIME_SPAN)
+            .map(Block::get_timestamp)
+            .collect();
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+
+    /// Returns the timestamp of a block produced now: the network-adjusted time, or the
+    /// median timestamp of the last blocks if the network time is earlier.
+    pub fn next_timestamp(&self) -> u128 {
+        self.network_time().max(self.median_time_past())
+    }
+
+    /// Checks that a block can extend the last block of this blockchain.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - The block to check.
+    ///
+    /// # Returns
+    ///
+    /// The chain state after applying the block.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first failed check.
+    fn validate_block(&self, block: &Block) -> Result<ChainState, String> {
+        let previous = self.get_last_block().ok_or("Blockchain is empty.")?;
+
+        // Check if the block’s previous hash matches the last block’s hash
+        if block.get_prev_hash() != previous.get_hash() {
+            return Err("Invalid previous hash.".to_string());
+        }
+
+        if block.get_height() != previous.get_height() + 1 {
+            return Err(format!("Invalid height {}.", block.get_height()));
+        }
+
+        // Recalculate hash and compare it to the stored hash
+        if block.get_hash() != block.calculate_hash() {
+            return Err("Block hash does not match its contents.".to_string());
+        }
+
+        let median = self.median_time_past();
+        if block.get_timestamp() < median {
+            return Err(format!(
+                "Timestamp {} is earlier than the median {} of the previous {} blocks.",
+                block.get_timestamp(), median, MEDIAN_TIME_SPAN
+            ));
+        }
+        let latest = self.network_time() + MAX_FUTURE_DRIFT.as_millis();
+        if block.get_timestamp() > latest {
+            return Err(format!("Timestamp {} is too far in the future (network time {}).", block.get_timestamp(), self.network_time()));
+        }
+
+        consensus::validate_producer(self, block)?;
+
+        let mut state = self.state.clone();
+        for tx in &block.transactions {
+            state.apply(tx)?;
+        }
+        Ok(state)
+    }
+
+    /// Rebuilds a blockchain by appending `blocks` one by one to a fresh genesis.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the first invalid block.
+    fn replay(blocks: Vec<Block>, spec: ChainSpec, time_offset: i64) -> Result<Self, String> {
+        let mut blockchain = Blockchain::new(spec);
+        blockchain.time_offset = time_offset;
+        let mut blocks = blocks.into_iter();
+
+        let genesis = blocks.next().ok_or("Blockchain is empty.")?;
+        if genesis.get_hash() != blockchain.blocks[0].get_hash() {
+            return Err("Unexpected genesis block.".to_string());
+        }
+
+        for block in blocks {
+            let height = block.get_height();
+            let state = blockchain
+                .validate_block(&block)
+                .map_err(|reason| format!("Block {} is invalid: {}", height, reason))?;
+            blockchain.state = state;
+            blockchain.blocks.push(block);
+        }
+        Ok(blockchain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity;
+
+    /// Builds a signed block extending the tip of `chain`.
+    fn signed_block(chain: &Blockchain, timestamp: u128) -> Block {
+        let tip = chain.get_last_block().unwrap();
+        let mut block = Block::new_block(tip.get_hash(), tip.get_height() + 1, timestamp, Vec::new());
+        block.sign(&identity::Keypair::generate_ed25519());
+        block
+    }
+
+    #[test]
+    fn median_time_past_covers_the_last_blocks() {
+        let mut chain = Blockchain::new(ChainSpec::default());
+        assert_eq!(chain.median_time_past(), 0);
+        for timestamp in [1_000, 5_000, 3_000] {
+            assert!(chain.add_block(signed_block(&chain, timestamp)));
+        }
+        // Sorted timestamps 0, 1000, 3000, 5000: the upper middle one.
+        assert_eq!(chain.median_time_past(), 3_000);
+
+        for timestamp in (1..=MEDIAN_TIME_SPAN as u128).map(|i| 10_000 + i) {
+            assert!(chain.add_block(signed_block(&chain, timestamp)));
+        }
+        // Only the last MEDIAN_TIME_SPAN blocks count.
+        assert_eq!(chain.median_time_past(), 10_000 + MEDIAN_TIME_SPAN as u128 / 2 + 1);
+    }
+
+    #[test]
+    fn block_may_not_be_earlier_than_the_median() {
+        let mut chain = Blockchain::new(ChainSpec::default());
+        for timestamp in [1_000, 2_000, 3_000] {
+            assert!(chain.add_block(signed_block(&chain, timestamp)));
+        }
+        assert_eq!(chain.median_time_past(), 2_000);
+        assert!(!chain.add_block(signed_block(&chain, 1_999)));
+        assert!(chain.add_block(signed_block(&chain, 2_000)));
+    }
+
+    #[test]
+    fn block_may_not_be_too_far_in_the_future() {
+        let mut chain = Blockchain::new(ChainSpec::default());
+        let too_late = chain.network_time() + MAX_FUTURE_DRIFT.as_millis() + 10_000;
+        assert!(!chain.add_block(signed_block(&chain, too_late)));
+        assert!(chain.add_block(signed_block(&chain, chain.network_time())));
+    }
+
+    #[test]
+    fn network_time_offset_moves_the_future_bound() {
+        let mut chain = Blockchain::new(ChainSpec::default());
+        let ahead = clock::local_time() + MAX_FUTURE_DRIFT.as_millis() + 30_000;
+        assert!(!chain.add_block(signed_block(&chain, ahead)));
+        chain.set_time_offset(60_000);
+        assert!(chain.add_block(signed_block(&chain, ahead)));
+    }
+
+    #[test]
+    fn next_timestamp_is_never_earlier_than_the_median() {
+        let mut chain = Blockchain::new(ChainSpec::default());
+        let now = chain.network_time();
+        assert!(chain.add_block(signed_block(&chain, now + 20_000)));
+        assert!(chain.add_block(signed_block(&chain, now + 30_000)));
+        assert!(chain.next_timestamp() >= chain.median_time_past());
+        assert_eq!(chain.median_time_past(), now + 20_000);
+    }
+
+    #[test]
+    fn unsigned_block_is_rejected() {
+        let mut chain = Blockchain::new(ChainSpec::default());
+        let tip = chain.get_last_block().unwrap();
+        let block = Block::new_block(tip.get_hash(), 1, 1_000, Vec::new());
+        assert!(!chain.add_block(block));
     }
 }
@@ -0,0 +1,84 @@
+//! This module defines the **chain specification** shared by every node of a network.
+//!
+//! The chain spec fixes the parameters all peers must agree on: the chain id,
+//! the genesis block and state, and the consensus rules used to validate new blocks.
+
+use std::{collections::BTreeMap, error::Error, fs, path::Path};
+
+use serde::{Serialize, Deserialize};
+
+/// The consensus rules a chain is produced under.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ConsensusKind {
+    /// Any node may produce a block at any height.
+    #[default]
+    Open,
+
+    /// Only the listed validators may produce blocks, taking turns by height.
+    ProofOfAuthority {
+        /// Hex-encoded Ed25519 public keys of the validators, in rotation order.
+        validators: Vec<String>,
+    },
+
+    /// The producer of each block is elected among the bonded validators,
+    /// with a probability proportional to their stake.
+    ProofOfStake {
+        /// Stake bonded at genesis per hex-encoded Ed25519 public key.
+        genesis_stakes: BTreeMap<String, u64>,
+    },
+}
+
+/// Describes a blockchain network.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainSpec {
+    /// Human readable identifier of the chain.
+    pub chain_id: String,
+
+    /// Timestamp of the genesis block (milliseconds since UNIX epoch).
+    ///
+    /// Fixing it in the spec makes every node derive the same genesis hash.
+    #[serde(default)]
+    pub genesis_timestamp: u128,
+
+    /// The consensus rules of the chain.
+    #[serde(default)]
+    pub consensus: ConsensusKind,
+
+    /// Unbonded balance per hex-encoded Ed25519 public key at genesis.
+    #[serde(default)]
+    pub balances: BTreeMap<String, u64>,
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        ChainSpec {
+            chain_id: "p2p_network".to_string(),
+            genesis_timestamp: 0,
+            consensus: ConsensusKind::Open,
+            balances: BTreeMap::new(),
+        }
+    }
+}
+
+impl ChainSpec {
+    /// Loads a chain spec from a JSON file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the chain spec file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not a valid chain spec.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let spec = ChainSpec::load("chain_spec.json").expect("Failed to load chain spec");
+    /// ```
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&data)?)
+    }
+}
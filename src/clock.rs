@@ -0,0 +1,147 @@
+//! This module implements the **network-adjusted time** of the node.
+//!
+//! Every peer reports its clock in the status it sends in the handshake. The node keeps
+//! the offset of each peer's clock from its own, and adjusts its time by the median of
+//! the offsets, the local clock counting as one more sample. A few peers with wrong
+//! clocks therefore cannot move the node's time, and the adjustment is capped: if the
+//! network disagrees with the local clock by more than [`MAX_ADJUSTMENT`], the local
+//! clock is used as is and should be checked.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::PeerId;
+
+/// Largest offset applied to the local clock.
+const MAX_ADJUSTMENT: Duration = Duration::from_secs(600);
+
+/// Largest offset of a peer's clock that is kept as a sample. Clocks further off are
+/// broken rather than skewed.
+const MAX_SAMPLE_OFFSET: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The offsets of the peers' clocks from the local clock.
+#[derive(Debug, Default)]
+pub struct NetworkClock {
+    /// Clock offset of each peer, in milliseconds (positive if the peer's clock is ahead).
+    offsets: HashMap<PeerId, i64>,
+}
+
+impl NetworkClock {
+    /// Records the clock a peer reported.
+    ///
+    /// The offset is measured when the status arrives, so it is off by the time the
+    /// status took to travel, usually a few milliseconds. A clock more than
+    /// [`MAX_SAMPLE_OFFSET`] away from the local clock is ignored.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - The peer.
+    /// * `peer_time` - The peer's clock, in milliseconds since the UNIX epoch.
+    pub fn on_peer_time(&mut self, peer_id: PeerId, peer_time: u128) {
+        let offset = i64::try_from(peer_time)
+            .map(|peer_time| peer_time.saturating_sub(local_time() as i64))
+            .unwrap_or(i64::MAX);
+        if offset.unsigned_abs() > MAX_SAMPLE_OFFSET.as_millis() as u64 {
+            println!("Ignored the clock of {}: it is more than {}s off.", peer_id, MAX_SAMPLE_OFFSET.as_secs());
+            self.offsets.remove(&peer_id);
+            return;
+        }
+        if offset.unsigned_abs() > MAX_ADJUSTMENT.as_millis() as u64 {
+            println!("Clock of {} differs from the local clock by {}s.", peer_id, offset / 1000);
+        }
+        self.offsets.insert(peer_id, offset);
+    }
+
+    /// Forgets the clock of a disconnected peer.
+    pub fn forget(&mut self, peer_id: &PeerId) {
+        self.offsets.remove(peer_id);
+    }
+
+    /// Returns the number of peers whose clock is known.
+    pub fn samples(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Returns the offset of the network-adjusted time from the local clock, in milliseconds.
+    ///
+    /// # Returns
+    ///
+    /// The median of the peers' offsets and the local clock's, or `0` if it exceeds [`MAX_ADJUSTMENT`].
+    pub fn offset(&self) -> i64 {
+        let mut offsets: Vec<i64> = self.offsets.values().copied().collect();
+        offsets.push(0);
+        offsets.sort_unstable();
+
+        let middle = offsets.len() / 2;
+        let median = if offsets.len().is_multiple_of(2) {
+            ((offsets[middle - 1] as i128 + offsets[middle] as i128) / 2) as i64
+        } else {
+            offsets[middle]
+        };
+        if median.unsigned_abs() > MAX_ADJUSTMENT.as_millis() as u64 {
+            return 0;
+        }
+        median
+    }
+}
+
+/// Returns the local clock, in milliseconds since the UNIX epoch.
+pub fn local_time() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_millis()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignores_extreme_peer_times() {
+        let mut clock = NetworkClock::default();
+        clock.on_peer_time(PeerId::random(), u128::MAX);
+        clock.on_peer_time(PeerId::random(), 1 << 63);
+        clock.on_peer_time(PeerId::random(), (1 << 63) - 1);
+        clock.on_peer_time(PeerId::random(), 0);
+        assert_eq!(clock.samples(), 0);
+        assert_eq!(clock.offset(), 0);
+    }
+
+    #[test]
+    fn median_of_extreme_offsets_does_not_overflow() {
+        let mut clock = NetworkClock::default();
+        clock.offsets.insert(PeerId::random(), i64::MAX);
+        clock.offsets.insert(PeerId::random(), i64::MAX);
+        clock.offsets.insert(PeerId::random(), i64::MAX);
+        assert_eq!(clock.offset(), 0);
+    }
+
+    #[test]
+    fn offset_is_the_median_including_the_local_clock() {
+        let mut clock = NetworkClock::default();
+        clock.offsets.insert(PeerId::random(), 1_000);
+        assert_eq!(clock.offset(), 500);
+        clock.offsets.insert(PeerId::random(), 2_000);
+        assert_eq!(clock.offset(), 1_000);
+    }
+
+    #[test]
+    fn offset_beyond_the_maximum_adjustment_is_not_applied() {
+        let mut clock = NetworkClock::default();
+        let far = MAX_ADJUSTMENT.as_millis() as i64 + 1_000;
+        clock.offsets.insert(PeerId::random(), far);
+        clock.offsets.insert(PeerId::random(), far);
+        assert_eq!(clock.offset(), 0);
+    }
+
+    #[test]
+    fn records_a_skewed_peer_clock() {
+        let mut clock = NetworkClock::default();
+        clock.on_peer_time(PeerId::random(), local_time() + 5_000);
+        assert_eq!(clock.samples(), 1);
+        assert!((2_000..=2_500).contains(&clock.offset()));
+    }
+}
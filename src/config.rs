@@ -0,0 +1,100 @@
+//! This module defines the **node configuration** loaded from a JSON file.
+//!
+//! Unlike the chain spec, which every node of a network shares, the node
+//! configuration holds settings local to one node, such as the peers it
+//! bootstraps from.
+
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use libp2p::{Multiaddr, PeerId};
+use serde::{Serialize, Deserialize};
+
+use crate::rate_limit::{MessageKind, Quota};
+
+/// Settings local to a node.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NodeConfig {
+    /// Peers used to bootstrap the DHT, as multiaddrs ending in `/p2p/<peer id>`.
+    #[serde(default)]
+    pub bootstrap_peers: Vec<String>,
+
+    /// Addresses to listen on, e.g. `/ip4/0.0.0.0/udp/4001/quic-v1`. The node listens
+    /// on random TCP, QUIC and WebSocket ports when none are given.
+    #[serde(default)]
+    pub listen_addrs: Vec<String>,
+
+    /// The only peers allowed to connect, as peer ids. Any peer may connect when
+    /// neither this nor `--allow` lists any.
+    #[serde(default)]
+    pub allowed_peers: Vec<String>,
+
+    /// Per-peer quotas overriding the default rate limit of a message kind.
+    #[serde(default)]
+    pub rate_limits: HashMap<MessageKind, Quota>,
+}
+
+impl NodeConfig {
+    /// Loads a node configuration from a JSON file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the configuration file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not a valid configuration or
+    /// sets a rate limit with a zero rate or burst.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let config = NodeConfig::load("node_config.json").expect("Failed to load node config");
+    /// ```
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let data = fs::read_to_string(path)?;
+        let config: NodeConfig = serde_json::from_str(&data)?;
+        for (kind, quota) in &config.rate_limits {
+            quota.validate().map_err(|e| format!("Invalid rate limit for {:?}: {}", kind, e))?;
+        }
+        Ok(config)
+    }
+
+    /// Parses the configured bootstrap peers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any entry is not a valid multiaddr.
+    pub fn bootstrap_addrs(&self) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+        let mut addrs = Vec::new();
+        for peer in &self.bootstrap_peers {
+            addrs.push(peer.parse()?);
+        }
+        Ok(addrs)
+    }
+
+    /// Parses the configured allowlist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any entry is not a valid peer id.
+    pub fn allowed_peers(&self) -> Result<Vec<PeerId>, Box<dyn Error>> {
+        let mut peers = Vec::new();
+        for peer in &self.allowed_peers {
+            peers.push(peer.parse()?);
+        }
+        Ok(peers)
+    }
+
+    /// Parses the configured listen addresses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any entry is not a valid multiaddr.
+    pub fn listen_addrs(&self) -> Result<Vec<Multiaddr>, Box<dyn Error>> {
+        let mut addrs = Vec::new();
+        for addr in &self.listen_addrs {
+            addrs.push(addr.parse()?);
+        }
+        Ok(addrs)
+    }
+}
@@ -0,0 +1,256 @@
+//! This module manages the node's **connections** to specific peers.
+//!
+//! It keeps the node connected to its bootstrap peers, redialing them with an
+//! exponential backoff when a connection drops or a dial fails, and remembers why
+//! the last dial to each peer failed. It also manages the allowlist of the only peers
+//! the node accepts connections from, once enabled. Banned peers are handled by the
+//! [`crate::peer_manager`].
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    swarm::{dial_opts::DialOpts, DialError, Swarm},
+    Multiaddr, PeerId,
+};
+
+use crate::network::CustomBehaviour;
+
+/// Delay before the first reconnection attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Upper bound of the reconnection delay.
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Shown by the allowlist commands when the node accepts any peer.
+const ALLOWLIST_DISABLED: &str = "No allowlist: any peer may connect. `Allow <peer id>`, `--allow` or `allowed_peers` in the node configuration enable it.";
+
+/// Interval at which [`ConnectionManager::tick`] should be called.
+pub const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Reconnection state of a bootstrap peer.
+#[derive(Debug)]
+struct BootstrapPeer {
+    /// The transport address of the peer.
+    addr: Multiaddr,
+
+    /// Delay before the next reconnection attempt.
+    backoff: Duration,
+
+    /// When to redial the peer, if a reconnection is scheduled.
+    next_dial: Option<Instant>,
+}
+
+/// Keeps bootstrap peers connected.
+#[derive(Debug, Default)]
+pub struct ConnectionManager {
+    /// Peers the node stays connected to.
+    bootstrap: HashMap<PeerId, BootstrapPeer>,
+
+    /// Why the last dial to each peer failed, until a connection succeeds.
+    failures: HashMap<PeerId, String>,
+}
+
+impl ConnectionManager {
+    /// Creates a connection manager with no bootstrap peers.
+    pub fn new() -> Self {
+        ConnectionManager::default()
+    }
+
+    /// Registers a peer the node should stay connected to.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - The bootstrap peer.
+    /// * `addr` - Its transport address.
+    pub fn add_bootstrap_peer(&mut self, peer_id: PeerId, addr: Multiaddr) {
+        self.bootstrap.insert(peer_id, BootstrapPeer {
+            addr,
+            backoff: INITIAL_BACKOFF,
+            next_dial: None,
+        });
+    }
+
+    /// Handles an established connection, resetting the peer's backoff.
+    pub fn on_connected(&mut self, peer_id: &PeerId) {
+        self.failures.remove(peer_id);
+        if let Some(peer) = self.bootstrap.get_mut(peer_id) {
+            peer.backoff = INITIAL_BACKOFF;
+            peer.next_dial = None;
+        }
+    }
+
+    /// Handles a lost connection or a failed dial, scheduling a reconnection
+    /// to bootstrap peers after their current backoff.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId) {
+        if let Some(peer) = self.bootstrap.get_mut(peer_id) {
+            if peer.next_dial.is_none() {
+                println!("Reconnecting to bootstrap peer {} in {:?}.", peer_id, peer.backoff);
+                peer.next_dial = Some(Instant::now() + peer.backoff);
+                peer.backoff = (peer.backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+
+    /// Handles a failed dial: records the error and schedules a reconnection to bootstrap peers.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - The peer that could not be reached.
+    /// * `error` - Why the dial failed.
+    pub fn on_dial_failed(&mut self, peer_id: &PeerId, error: &DialError) {
+        self.failures.insert(*peer_id, error.to_string());
+        self.on_disconnected(peer_id);
+    }
+
+    /// Returns why the last dial to a peer failed, if it has not connected since.
+    pub fn last_failure(&self, peer_id: &PeerId) -> Option<&str> {
+        self.failures.get(peer_id).map(String::as_str)
+    }
+
+    /// Dials a peer by address on user request.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `addr` - The multiaddr to dial.
+    pub fn connect(&mut self, swarm: &mut Swarm<CustomBehaviour>, addr: Multiaddr) {
+        match swarm.dial(addr.clone()) {
+            Ok(()) => println!("Dialing {}", addr),
+            Err(e) => println!("Failed to dial {}: {}", addr, e),
+        }
+    }
+
+    /// Closes all connections to a peer on user request.
+    ///
+    /// A manually disconnected bootstrap peer is not redialed.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The peer to disconnect from.
+    pub fn disconnect(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId) {
+        if self.bootstrap.remove(&peer_id).is_some() {
+            println!("Stopped reconnecting to bootstrap peer {}.", peer_id);
+        }
+        match swarm.disconnect_peer_id(peer_id) {
+            Ok(()) => println!("Disconnecting from {}", peer_id),
+            Err(()) => println!("Not connected to {}", peer_id),
+        }
+    }
+
+    /// Adds a peer to the allowlist on user request.
+    ///
+    /// The first peer allowed on a node without an allowlist enables it: the
+    /// connections to all other peers are closed.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The peer to allow.
+    pub fn allow(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId) {
+        let was_enabled = swarm.behaviour().allowed.is_enabled();
+        if swarm.behaviour_mut().allowed.allow_peer(peer_id) {
+            println!("Allowed {}.", peer_id);
+        } else {
+            println!("{} is already allowed.", peer_id);
+        }
+        if was_enabled {
+            return;
+        }
+
+        let unlisted: Vec<PeerId> = swarm.connected_peers()
+            .filter(|peer| !swarm.behaviour().is_allowed(peer))
+            .copied()
+            .collect();
+        println!("Allowlist enabled: closing the connections to {} unlisted peers.", unlisted.len());
+        for peer in unlisted {
+            swarm.behaviour_mut().kad.remove_peer(&peer);
+            let _ = swarm.disconnect_peer_id(peer);
+        }
+    }
+
+    /// Removes a peer from the allowlist on user request, closing its connections and
+    /// dropping it from the DHT routing table.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The peer to disallow.
+    pub fn disallow(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId) {
+        if !swarm.behaviour().allowed.is_enabled() {
+            println!("{}", ALLOWLIST_DISABLED);
+            return;
+        }
+        if swarm.behaviour_mut().allowed.disallow_peer(peer_id) {
+            swarm.behaviour_mut().kad.remove_peer(&peer_id);
+            println!("Disallowed {}.", peer_id);
+        } else {
+            println!("{} is not in the allowlist.", peer_id);
+        }
+    }
+
+    /// Prints the peers of the allowlist.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    pub fn print_allowlist(&self, swarm: &Swarm<CustomBehaviour>) {
+        let allowed = &swarm.behaviour().allowed;
+        if !allowed.is_enabled() {
+            println!("{}", ALLOWLIST_DISABLED);
+            return;
+        }
+        println!("Allowed peers:");
+        for peer_id in allowed.allowed_peers() {
+            let state = if swarm.is_connected(peer_id) { "connected" } else { "not connected" };
+            println!("{} ({})", peer_id, state);
+        }
+    }
+
+    /// Redials bootstrap peers whose backoff elapsed.
+    ///
+    /// Dials to banned peers are refused by the swarm and retried after the backoff.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    pub fn tick(&mut self, swarm: &mut Swarm<CustomBehaviour>) {
+        let now = Instant::now();
+        for (peer_id, peer) in self.bootstrap.iter_mut() {
+            if peer.next_dial.is_none_or(|at| at > now) {
+                continue;
+            }
+            peer.next_dial = None;
+            let opts = DialOpts::peer_id(*peer_id).addresses(vec![peer.addr.clone()]).build();
+            if swarm.dial(opts).is_err() {
+                peer.next_dial = Some(now + peer.backoff);
+            }
+        }
+    }
+}
+
+/// Parses a duration such as `90`, `90s`, `15m`, `2h` or `1d`. A bare number is in seconds.
+///
+/// # Errors
+///
+/// Returns the reason the input is not a number with an optional unit, or is too large.
+pub fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (value, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(split) => input.split_at(split),
+        None => (input, "s"),
+    };
+    let value: u64 = value.parse().map_err(|_| format!("invalid duration {}", input))?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(format!("unknown duration unit {}", unit)),
+    };
+    let seconds = value.checked_mul(multiplier).ok_or_else(|| format!("duration {} is too large", input))?;
+    Ok(Duration::from_secs(seconds))
+}
@@ -0,0 +1,246 @@
+//! This module implements the **consensus rules** that decide who may produce a block.
+//!
+//! Every block must be signed by its producer. Who may produce a block is selected
+//! by the [`ConsensusKind`] of the chain spec:
+//! - **Open**: any node may append a block.
+//! - **Proof-of-authority**: validators listed in the chain spec take turns
+//!   round-robin by block height and must sign the blocks they produce.
+//! - **Proof-of-stake**: the producer of each block is elected among the bonded
+//!   validators, weighted by stake, and must sign the block.
+
+use std::collections::BTreeMap;
+
+use sha2::{Sha256, Digest};
+
+use crate::block::{Block, BlockHeader};
+use crate::blockchain::Blockchain;
+use crate::chain_spec::{ChainSpec, ConsensusKind};
+
+/// Number of most recent block hashes mixed into the proof-of-stake election seed.
+const SEED_BLOCKS: usize = 3;
+
+/// Returns the validator expected to produce the next block of `chain`.
+///
+/// # Returns
+///
+/// - `Some(String)` with the hex-encoded public key of the producer in turn.
+/// - `None` if the chain has no fixed producer for the next height
+///   (an open chain, or no validator with stake).
+pub fn next_producer(chain: &Blockchain) -> Option<String> {
+    let height = chain.get_blocks().len();
+
+    match &chain.get_spec().consensus {
+        ConsensusKind::Open => None,
+        ConsensusKind::ProofOfAuthority { validators } => authority_at(validators, height).map(str::to_string),
+        ConsensusKind::ProofOfStake { .. } => {
+            elect_leader(chain.get_state().get_stakes(), &election_seed(chain.get_blocks(), height))
+                .map(str::to_string)
+        }
+    }
+}
+
+/// Returns the proof-of-authority validator in turn at `height`, or `None` if there is no validator.
+fn authority_at(validators: &[String], height: usize) -> Option<&str> {
+    if validators.is_empty() {
+        return None;
+    }
+    Some(validators[height % validators.len()].as_str())
+}
+
+/// Checks the producer of a block header against the consensus rules that do not
+/// depend on the chain state.
+///
+/// Under proof-of-authority, the producer must be the validator in turn at the
+/// header's height. Proof-of-stake leaders are elected from the stakes, which are
+/// only known once the previous blocks are applied, so they are checked when the
+/// block is added.
+///
+/// # Arguments
+///
+/// * `spec` - The chain spec of the chain.
+/// * `header` - The header to check.
+///
+/// # Errors
+///
+/// Returns a description of the violated rule.
+pub fn validate_header_producer(spec: &ChainSpec, header: &BlockHeader) -> Result<(), String> {
+    let producer = header.producer.as_deref().ok_or("Block is not signed.")?;
+    if let ConsensusKind::ProofOfAuthority { validators } = &spec.consensus {
+        if authority_at(validators, header.height) != Some(producer) {
+            return Err(format!("Producer {} is not the validator in turn at height {}.", producer, header.height));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the voting weight of each validator for the finality gadget.
+///
+/// Proof-of-authority validators weigh `1` each, proof-of-stake validators weigh
+/// their bonded stake. An open chain has no validators, so nothing is finalized.
+pub fn voting_weights(chain: &Blockchain) -> BTreeMap<String, u64> {
+    match &chain.get_spec().consensus {
+        ConsensusKind::Open => BTreeMap::new(),
+        ConsensusKind::ProofOfAuthority { validators } => validators.iter().map(|v| (v.clone(), 1)).collect(),
+        ConsensusKind::ProofOfStake { .. } => chain.get_state().get_stakes().clone(),
+    }
+}
+
+/// Derives the election seed of a slot from the hashes of the most recent blocks.
+///
+/// # Arguments
+///
+/// * `blocks` - The blocks preceding the slot.
+/// * `height` - The height of the slot.
+fn election_seed(blocks: &[Block], height: usize) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for block in blocks.iter().rev().take(SEED_BLOCKS) {
+        hasher.update(&block.hash);
+    }
+    hasher.update(height.to_string());
+    hasher.finalize().into()
+}
+
+/// Elects a leader among `stakes` with a probability proportional to its stake.
+///
+/// The seed selects a point in `[0, total stake)`; the leader is the validator whose
+/// cumulative stake range, walked in public key order, contains that point.
+///
+/// # Returns
+///
+/// - `Some(&str)` with the elected validator's public key.
+/// - `None` if no stake is bonded.
+fn elect_leader<'a>(stakes: &'a BTreeMap<String, u64>, seed: &[u8; 32]) -> Option<&'a str> {
+    let total: u128 = stakes.values().map(|stake| *stake as u128).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = u128::from_be_bytes(seed[..16].try_into().expect("Seed is 32 bytes")) % total;
+    for (validator, stake) in stakes {
+        let stake = *stake as u128;
+        if point < stake {
+            return Some(validator.as_str());
+        }
+        point -= stake;
+    }
+    None
+}
+
+/// Checks that a block was produced according to the consensus rules of the chain.
+///
+/// # Arguments
+///
+/// * `chain` - The blockchain the block extends.
+/// * `block` - The block to check.
+///
+/// # Errors
+///
+/// Returns a description of the violated rule if the block is not acceptable.
+pub fn validate_producer(chain: &Blockchain, block: &Block) -> Result<(), String> {
+    let producer = block.producer.as_deref().ok_or("Block is not signed.")?;
+
+    match &chain.get_spec().consensus {
+        ConsensusKind::ProofOfAuthority { validators } => {
+            if !validators.iter().any(|v| v == producer) {
+                return Err(format!("Producer {} is not in the validator set.", producer));
+            }
+            if next_producer(chain).as_deref() != Some(producer) {
+                return Err(format!("Producer {} is out of turn at height {}.", producer, block.get_height()));
+            }
+        }
+        ConsensusKind::ProofOfStake { .. } => match next_producer(chain) {
+            Some(leader) if leader == producer => {}
+            Some(_) => {
+                return Err(format!("Producer {} is not the elected leader at height {}.", producer, block.get_height()));
+            }
+            None => return Err("No validator has bonded stake.".to_string()),
+        },
+        ConsensusKind::Open => {}
+    }
+
+    if !block.verify_signature() {
+        return Err("Invalid block signature.".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators() -> Vec<String> {
+        vec!["a".to_string(), "b".to_string(), "c".to_string()]
+    }
+
+    fn poa_spec() -> ChainSpec {
+        ChainSpec {
+            consensus: ConsensusKind::ProofOfAuthority { validators: validators() },
+            ..ChainSpec::default()
+        }
+    }
+
+    fn header(height: usize, producer: Option<&str>) -> BlockHeader {
+        let mut block = Block::new_block("0".repeat(64), height, 0, Vec::new());
+        block.producer = producer.map(str::to_string);
+        block.header()
+    }
+
+    /// Returns a seed that selects `point` in the stake range.
+    fn seed(point: u128) -> [u8; 32] {
+        let mut seed = [0; 32];
+        seed[..16].copy_from_slice(&point.to_be_bytes());
+        seed
+    }
+
+    #[test]
+    fn authorities_take_turns_by_height() {
+        let validators = validators();
+        let turns: Vec<&str> = (0..7).filter_map(|height| authority_at(&validators, height)).collect();
+        assert_eq!(turns, ["a", "b", "c", "a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn no_authority_without_validators() {
+        assert_eq!(authority_at(&[], 0), None);
+        assert_eq!(authority_at(&[], 5), None);
+    }
+
+    #[test]
+    fn header_producer_must_be_in_turn() {
+        let spec = poa_spec();
+        assert!(validate_header_producer(&spec, &header(4, Some("b"))).is_ok());
+        assert!(validate_header_producer(&spec, &header(4, Some("a"))).is_err());
+        assert!(validate_header_producer(&spec, &header(4, Some("d"))).is_err());
+        assert!(validate_header_producer(&spec, &header(4, None)).is_err());
+    }
+
+    #[test]
+    fn open_chain_accepts_any_signed_header() {
+        let spec = ChainSpec::default();
+        assert!(validate_header_producer(&spec, &header(4, Some("d"))).is_ok());
+        assert!(validate_header_producer(&spec, &header(4, None)).is_err());
+    }
+
+    #[test]
+    fn leader_is_elected_by_stake_range() {
+        let stakes = BTreeMap::from([("a".to_string(), 1), ("b".to_string(), 3)]);
+        assert_eq!(elect_leader(&stakes, &seed(0)), Some("a"));
+        assert_eq!(elect_leader(&stakes, &seed(1)), Some("b"));
+        assert_eq!(elect_leader(&stakes, &seed(3)), Some("b"));
+        assert_eq!(elect_leader(&stakes, &seed(4)), Some("a"));
+    }
+
+    #[test]
+    fn no_leader_without_stake() {
+        assert_eq!(elect_leader(&BTreeMap::new(), &seed(0)), None);
+        let stakes = BTreeMap::from([("a".to_string(), 0)]);
+        assert_eq!(elect_leader(&stakes, &seed(0)), None);
+    }
+
+    #[test]
+    fn large_stakes_do_not_overflow_the_election() {
+        let stakes = BTreeMap::from([("a".to_string(), u64::MAX), ("b".to_string(), u64::MAX)]);
+        assert_eq!(elect_leader(&stakes, &seed(u64::MAX as u128 - 1)), Some("a"));
+        assert_eq!(elect_leader(&stakes, &seed(u64::MAX as u128)), Some("b"));
+    }
+}
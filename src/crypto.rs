@@ -0,0 +1,122 @@
+//! This module contains the **node identity** and the **signing helpers** used by blocks and consensus.
+//!
+//! Public keys and signatures are exchanged as lowercase hex strings so they can be
+//! embedded directly in the JSON representation of blocks and chain specs.
+
+use std::{error::Error, fs, io::Write, path::Path};
+
+use data_encoding::HEXLOWER;
+use libp2p::{identity, pnet::PreSharedKey, PeerId};
+
+/// Loads the node's identity keypair from `path`, creating it on first run.
+///
+/// The keypair is stored in the libp2p protobuf encoding. A new key file is only
+/// readable and writable by its owner, since it holds the node's secret key.
+///
+/// # Arguments
+///
+/// * `path` - Path to the key file.
+///
+/// # Errors
+///
+/// Returns an error if the key file cannot be read, decoded or created.
+///
+/// # Example
+///
+/// ```rust
+/// let keypair = load_or_create_keypair("node.key").expect("Failed to load node key");
+/// ```
+pub fn load_or_create_keypair(path: impl AsRef<Path>) -> Result<identity::Keypair, Box<dyn Error>> {
+    let path = path.as_ref();
+    if path.exists() {
+        let bytes = fs::read(path)?;
+        return Ok(identity::Keypair::from_protobuf_encoding(&bytes)?);
+    }
+
+    let keypair = identity::Keypair::generate_ed25519();
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    file.write_all(&keypair.to_protobuf_encoding()?)?;
+
+    println!("Generated new node key at {}", path.display());
+    Ok(keypair)
+}
+
+/// Loads the pre-shared key of a private network from a swarm key file.
+///
+/// The file uses the format of IPFS swarm keys:
+///
+/// ```text
+/// /key/swarm/psk/1.0.0/
+/// /base16/
+/// <64 hex characters>
+/// ```
+///
+/// # Arguments
+///
+/// * `path` - Path to the swarm key file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or is not a valid swarm key.
+pub fn load_swarm_key(path: impl AsRef<Path>) -> Result<PreSharedKey, Box<dyn Error>> {
+    let data = fs::read_to_string(path)?;
+    Ok(data.trim().parse()?)
+}
+
+/// Encodes the Ed25519 public key of a keypair as a hex string.
+///
+/// # Returns
+///
+/// - `Some(String)` with the 32-byte public key in hex.
+/// - `None` if the keypair is not an Ed25519 keypair.
+pub fn encode_public_key(keypair: &identity::Keypair) -> Option<String> {
+    let public = keypair.public().try_into_ed25519().ok()?;
+    Some(HEXLOWER.encode(&public.to_bytes()))
+}
+
+/// Signs a message and returns the signature as a hex string.
+///
+/// # Arguments
+///
+/// * `keypair` - The keypair used for signing.
+/// * `msg` - The message to sign.
+pub fn sign(keypair: &identity::Keypair, msg: &[u8]) -> Option<String> {
+    keypair.sign(msg).ok().map(|sig| HEXLOWER.encode(&sig))
+}
+
+/// Verifies a hex-encoded signature against a hex-encoded Ed25519 public key.
+///
+/// # Returns
+///
+/// `true` only if both encodings are valid and the signature matches the message.
+pub fn verify(public_key: &str, msg: &[u8], signature: &str) -> bool {
+    let Ok(key_bytes) = HEXLOWER.decode(public_key.as_bytes()) else {
+        return false;
+    };
+    let Ok(sig_bytes) = HEXLOWER.decode(signature.as_bytes()) else {
+        return false;
+    };
+    match identity::ed25519::PublicKey::try_from_bytes(&key_bytes) {
+        Ok(key) => key.verify(msg, &sig_bytes),
+        Err(_) => false,
+    }
+}
+
+/// Derives the libp2p `PeerId` of a hex-encoded Ed25519 public key.
+///
+/// # Returns
+///
+/// - `Some(PeerId)` of the node owning the key.
+/// - `None` if the key is not a valid Ed25519 public key.
+pub fn peer_id_of(public_key: &str) -> Option<PeerId> {
+    let key_bytes = HEXLOWER.decode(public_key.as_bytes()).ok()?;
+    let key = identity::ed25519::PublicKey::try_from_bytes(&key_bytes).ok()?;
+    Some(identity::PublicKey::from(key).to_peer_id())
+}
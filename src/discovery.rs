@@ -0,0 +1,227 @@
+//! This module implements **peer discovery** beyond the local network segment.
+//!
+//! Peers found by mDNS or configured as bootstrap peers are fed into a **Kademlia DHT**.
+//! The node then keeps discovering peers through periodic random walks, and persists
+//! its routing table so it can rejoin the network after a restart.
+
+use std::{error::Error, fs, path::Path, time::Duration};
+
+use libp2p::{
+    kad,
+    multiaddr::Protocol,
+    swarm::{dial_opts::DialOpts, DialError, Swarm},
+    Multiaddr, PeerId, StreamProtocol,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::network::CustomBehaviour;
+
+/// The Kademlia protocol name, kept distinct from the public IPFS DHT.
+const KAD_PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p_blockchain/kad/1.0.0");
+
+/// Interval between two random walks through the DHT.
+pub const RANDOM_WALK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A routing table entry as stored on disk.
+#[derive(Serialize, Deserialize, Debug)]
+struct RoutingEntry {
+    /// The peer id of the entry.
+    peer_id: String,
+
+    /// The known addresses of the peer.
+    addresses: Vec<String>,
+}
+
+/// Creates the Kademlia behaviour of the local node.
+///
+/// The node always runs in server mode, so peers on private networks
+/// answer DHT queries without first confirming an external address.
+///
+/// # Arguments
+///
+/// * `local_peer_id` - The peer id of the local node.
+pub fn new_kademlia(local_peer_id: PeerId) -> kad::Behaviour<kad::store::MemoryStore> {
+    let config = kad::Config::new(KAD_PROTOCOL);
+    let store = kad::store::MemoryStore::new(local_peer_id);
+    let mut kademlia = kad::Behaviour::with_config(local_peer_id, store, config);
+    kademlia.set_mode(Some(kad::Mode::Server));
+    kademlia
+}
+
+/// Splits a multiaddr ending in `/p2p/<peer id>` into the peer id and its transport address.
+///
+/// # Returns
+///
+/// - `Some((PeerId, Multiaddr))` if the multiaddr names a peer.
+/// - `None` if the multiaddr has no trailing `/p2p` component.
+pub fn split_peer_addr(addr: &Multiaddr) -> Option<(PeerId, Multiaddr)> {
+    let mut transport = addr.clone();
+    match transport.pop() {
+        Some(Protocol::P2p(peer_id)) => Some((peer_id, transport)),
+        _ => None,
+    }
+}
+
+/// Adds a discovered peer to the DHT and connects to it.
+///
+/// Peers missing from the allowlist, if one is configured, are ignored.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `peer_id` - The discovered peer.
+/// * `addr` - The address the peer was discovered at.
+pub fn add_peer(swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId, addr: Multiaddr) {
+    if !swarm.behaviour().is_allowed(&peer_id) {
+        println!("Ignoring peer {}: not in the allowlist.", peer_id);
+        return;
+    }
+    swarm.behaviour_mut().kad.add_address(&peer_id, addr.clone());
+
+    // Peers we are already connected or dialing to are skipped by the dial condition.
+    let opts = DialOpts::peer_id(peer_id).addresses(vec![addr]).build();
+    match swarm.dial(opts) {
+        Ok(()) | Err(DialError::DialPeerConditionFalse(_)) => {}
+        Err(e) => println!("Failed to dial {}: {}", peer_id, e),
+    }
+}
+
+/// Bootstraps the DHT from the given peers.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `bootstrap_peers` - Multiaddrs ending in `/p2p/<peer id>`.
+pub fn bootstrap(swarm: &mut Swarm<CustomBehaviour>, bootstrap_peers: &[Multiaddr]) {
+    for addr in bootstrap_peers {
+        match split_peer_addr(addr) {
+            Some((peer_id, transport)) => add_peer(swarm, peer_id, transport),
+            None => println!("Ignoring bootstrap peer without /p2p/<peer id>: {}", addr),
+        }
+    }
+
+    if swarm.behaviour_mut().kad.bootstrap().is_err() {
+        println!("No known peers to bootstrap the DHT from.");
+    }
+}
+
+/// Starts a random walk: a DHT lookup for a random peer id, which fills
+/// the routing table with the peers met along the way.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+pub fn random_walk(swarm: &mut Swarm<CustomBehaviour>) {
+    swarm.behaviour_mut().kad.get_closest_peers(PeerId::random());
+}
+
+/// Returns the peers of the DHT routing table with their known addresses.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+pub fn dht_peers(swarm: &mut Swarm<CustomBehaviour>) -> Vec<(PeerId, Vec<Multiaddr>)> {
+    let mut peers = Vec::new();
+    for bucket in swarm.behaviour_mut().kad.kbuckets() {
+        for entry in bucket.iter() {
+            let addresses = entry.node.value.iter().cloned().collect();
+            peers.push((*entry.node.key.preimage(), addresses));
+        }
+    }
+    peers
+}
+
+/// Writes the DHT routing table to a JSON file.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `path` - Path to the routing table file.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written.
+pub fn save_routing_table(swarm: &mut Swarm<CustomBehaviour>, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
+    let entries: Vec<RoutingEntry> = dht_peers(swarm)
+        .into_iter()
+        .map(|(peer_id, addresses)| RoutingEntry {
+            peer_id: peer_id.to_string(),
+            addresses: addresses.iter().map(|a| a.to_string()).collect(),
+        })
+        .collect();
+    fs::write(path, serde_json::to_string_pretty(&entries)?)?;
+    Ok(())
+}
+
+/// Restores a DHT routing table saved by [`save_routing_table`].
+///
+/// A missing file is not an error: the node simply starts with an empty table.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `path` - Path to the routing table file.
+///
+/// # Returns
+///
+/// The number of restored peers.
+///
+/// # Errors
+///
+/// Returns an error if the file exists but cannot be read or parsed. Invalid peer ids
+/// and addresses are skipped.
+pub fn load_routing_table(swarm: &mut Swarm<CustomBehaviour>, path: impl AsRef<Path>) -> Result<usize, Box<dyn Error>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let entries: Vec<RoutingEntry> = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let mut restored = 0;
+    for entry in entries {
+        // Corrupt entries are skipped one by one, so the rest of the table is kept.
+        let Ok(peer_id) = entry.peer_id.parse::<PeerId>() else {
+            println!("Skipped invalid peer id {} in the DHT routing table.", entry.peer_id);
+            continue;
+        };
+        if !swarm.behaviour().is_allowed(&peer_id) {
+            continue;
+        }
+        let mut added = false;
+        for addr in entry.addresses {
+            match addr.parse::<Multiaddr>() {
+                Ok(addr) => {
+                    swarm.behaviour_mut().kad.add_address(&peer_id, addr);
+                    added = true;
+                }
+                Err(_) => println!("Skipped invalid address {} of {} in the DHT routing table.", addr, peer_id),
+            }
+        }
+        if added {
+            restored += 1;
+        }
+    }
+    Ok(restored)
+}
+
+/// Handles events of the Kademlia DHT.
+///
+/// Newly discovered peers are dialed so they can join the GossipSub mesh. Peers
+/// missing from the allowlist are dropped from the routing table instead.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `event` - The Kademlia event.
+pub fn handle_kad_event(swarm: &mut Swarm<CustomBehaviour>, event: kad::Event) {
+    if let kad::Event::RoutingUpdated { peer, is_new_peer: true, .. } = event {
+        if !swarm.behaviour().is_allowed(&peer) {
+            swarm.behaviour_mut().kad.remove_peer(&peer);
+            return;
+        }
+        println!("DHT discovered peer: {}", peer);
+        if !swarm.is_connected(&peer) {
+            let _ = swarm.dial(DialOpts::peer_id(peer).build());
+        }
+    }
+}
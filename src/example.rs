@@ -1,21 +1,31 @@
 mod block;
 mod blockchain;
+mod chain_spec;
+mod clock;
+mod consensus;
+mod crypto;
+mod state;
+mod transaction;
 use crate::block::Block;
 use crate::blockchain::Blockchain;
+use crate::chain_spec::ChainSpec;
+use libp2p::identity;
 use std::fs;
 use std::io::{self, Write};
 
 fn main() {
     // Step 1: Initialize a new blockchain with the Genesis Block
-    let mut blockchain = Blockchain::new();
+    let mut blockchain = Blockchain::new(ChainSpec::default());
     println!("🚀 Blockchain initialized with Genesis Block:");
     print_block_details(blockchain.get_last_block().unwrap());
 
-    // Step 2: Add multiple blocks to the blockchain
+    // Step 2: Add multiple blocks to the blockchain, signed by a freshly generated producer key
     println!("\n🔗 Adding new blocks...");
+    let producer_key = identity::Keypair::generate_ed25519();
     for i in 1..=3 {
         let prev_hash = blockchain.get_last_block().unwrap().get_hash();
-        let new_block = Block::new_block(prev_hash, i);
+        let mut new_block = Block::new_block(prev_hash, i, blockchain.next_timestamp(), Vec::new());
+        new_block.sign(&producer_key);
         blockchain.add_block(new_block);
     }
 
@@ -35,10 +45,11 @@ fn main() {
     println!("\n📂 Loading blockchain from file...");
     let loaded_json = fs::read_to_string(filename).expect("Failed to read file");
     let loaded_blocks: Vec<Block> = serde_json::from_str(&loaded_json).expect("Failed to deserialize blockchain");
-    let loaded_blockchain = Blockchain::from_blocks(loaded_blocks);
+    let mut loaded_blockchain = Blockchain::new(ChainSpec::default());
 
-    // Step 6: Validate blockchain integrity
-    println!("\n✅ Blockchain validity check: {}", loaded_blockchain.is_valid());
+    // Step 6: Validate blockchain integrity by replaying the loaded blocks
+    let valid = loaded_blockchain.replace_chain(loaded_blocks);
+    println!("\n✅ Blockchain validity check: {}", valid);
 
     // Optional: User interaction
     println!("\n🔍 Do you want to inspect a block? (Enter block index or `exit`)");
@@ -0,0 +1,352 @@
+//! This module implements the **BFT finality gadget**.
+//!
+//! Validators gossip signed votes for the blocks they add to their chain, in two rounds:
+//! - a **prevote** as soon as a block is added, and
+//! - a **precommit** once prevotes for that block exceed 2/3 of the voting weight.
+//!
+//! A block is final once precommits for it exceed 2/3 of the voting weight. The
+//! blockchain never reorganizes below its last finalized block.
+//!
+//! A validator casts one vote per round and height. A second vote for another block
+//! is **equivocation**: it is rejected, so equivocating validators cannot crowd out the
+//! votes of honest ones.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use libp2p::identity;
+use serde::{Serialize, Deserialize};
+
+use crate::blockchain::Blockchain;
+use crate::consensus;
+use crate::crypto;
+
+/// How many blocks beyond the local tip votes are accepted for. Votes may arrive
+/// shortly before their block; votes further ahead are not relayed.
+pub const VOTE_WINDOW: usize = 8;
+
+/// The voting round a vote belongs to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VoteKind {
+    /// First round: the voter has added the block to its chain.
+    Prevote,
+
+    /// Second round: the voter has seen a 2/3 prevote quorum for the block.
+    Precommit,
+}
+
+/// A validator's signed vote for a block.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Vote {
+    /// The voting round.
+    pub kind: VoteKind,
+
+    /// The height of the block voted for.
+    pub height: usize,
+
+    /// The hash of the block voted for.
+    pub block_hash: String,
+
+    /// The hex-encoded Ed25519 public key of the voter.
+    pub voter: String,
+
+    /// The voter's hex-encoded signature over the vote.
+    pub signature: String,
+}
+
+impl Vote {
+    /// Creates a vote signed by `keypair`.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(Vote)` carrying a valid signature.
+    /// - `None` if the keypair is not an Ed25519 keypair.
+    pub fn new_signed(keypair: &identity::Keypair, kind: VoteKind, height: usize, block_hash: String) -> Option<Vote> {
+        let mut vote = Vote {
+            kind,
+            height,
+            block_hash,
+            voter: crypto::encode_public_key(keypair)?,
+            signature: String::new(),
+        };
+        vote.signature = crypto::sign(keypair, vote.payload().as_bytes())?;
+        Some(vote)
+    }
+
+    /// Returns the message covered by the voter's signature.
+    fn payload(&self) -> String {
+        format!("{:?}/{}/{}", self.kind, self.height, self.block_hash)
+    }
+
+    /// Verifies the voter's signature.
+    pub fn verify_signature(&self) -> bool {
+        crypto::verify(&self.voter, self.payload().as_bytes(), &self.signature)
+    }
+}
+
+/// Tracks votes and finalizes blocks of the local blockchain.
+pub struct FinalityGadget {
+    /// The local node's keypair, used to sign its own votes.
+    keypair: identity::Keypair,
+
+    /// Voters per round and block, keyed by `(height, block hash)`.
+    votes: BTreeMap<(usize, String), HashMap<VoteKind, HashSet<String>>>,
+
+    /// The block hash each voter voted for, keyed by `(height, round, voter)`.
+    cast: HashMap<(usize, VoteKind, String), String>,
+
+    /// Rounds and heights the local node already voted in, so it never votes twice.
+    voted: HashSet<(VoteKind, usize)>,
+}
+
+impl FinalityGadget {
+    /// Creates a finality gadget voting with the given keypair.
+    ///
+    /// # Arguments
+    ///
+    /// * `keypair` - The local node's keypair.
+    pub fn new(keypair: identity::Keypair) -> Self {
+        FinalityGadget {
+            keypair,
+            votes: BTreeMap::new(),
+            cast: HashMap::new(),
+            voted: HashSet::new(),
+        }
+    }
+
+    /// Handles a block that was added to the local blockchain.
+    ///
+    /// If the local node is a validator, it prevotes for the block.
+    ///
+    /// # Arguments
+    ///
+    /// * `chain` - The local blockchain.
+    /// * `height` - The height of the added block.
+    ///
+    /// # Returns
+    ///
+    /// The local votes to gossip to the network.
+    pub fn on_block_added(&mut self, chain: &mut Blockchain, height: usize) -> Vec<Vote> {
+        let Some(block_hash) = chain.get_blocks().get(height).map(|b| b.get_hash()) else {
+            return Vec::new();
+        };
+
+        let mut outgoing = Vec::new();
+        if let Some(vote) = self.own_vote(chain, VoteKind::Prevote, height, block_hash.clone()) {
+            outgoing.push(vote);
+        }
+
+        // Votes may have arrived before the block did.
+        self.process(chain, outgoing.clone(), &mut outgoing);
+        self.check_quorums(chain, height, &block_hash, &mut outgoing);
+        outgoing
+    }
+
+    /// Handles a vote received from the network.
+    ///
+    /// # Arguments
+    ///
+    /// * `chain` - The local blockchain.
+    /// * `vote` - The received vote.
+    ///
+    /// # Returns
+    ///
+    /// The local votes to gossip to the network in response.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the vote was rejected: an invalid signature, or a vote of a
+    /// validator that already voted for another block in the same round and height.
+    pub fn on_vote(&mut self, chain: &mut Blockchain, vote: Vote) -> Result<Vec<Vote>, String> {
+        if !vote.verify_signature() {
+            return Err(format!("invalid signature from {}.", vote.voter));
+        }
+        if let Some(voted_for) = self.cast.get(&(vote.height, vote.kind, vote.voter.clone())) {
+            if *voted_for != vote.block_hash {
+                return Err(format!(
+                    "{} equivocated: {:?} for {} and {} at height {}.",
+                    vote.voter, vote.kind, voted_for, vote.block_hash, vote.height
+                ));
+            }
+        }
+
+        let mut outgoing = Vec::new();
+        self.process(chain, vec![vote], &mut outgoing);
+        Ok(outgoing)
+    }
+
+    /// Records `pending` votes, casting local precommits and finalizing blocks as quorums are reached.
+    fn process(&mut self, chain: &mut Blockchain, mut pending: Vec<Vote>, outgoing: &mut Vec<Vote>) {
+        while let Some(vote) = pending.pop() {
+            if vote.height <= chain.finalized_height() {
+                continue;
+            }
+            if !consensus::voting_weights(chain).contains_key(&vote.voter) {
+                continue;
+            }
+            // Only the first vote of a voter per round and height counts.
+            let key = (vote.height, vote.kind, vote.voter.clone());
+            if self.cast.contains_key(&key) {
+                continue;
+            }
+            self.cast.insert(key, vote.block_hash.clone());
+
+            self.votes
+                .entry((vote.height, vote.block_hash.clone()))
+                .or_default()
+                .entry(vote.kind)
+                .or_default()
+                .insert(vote.voter.clone());
+
+            let before = outgoing.len();
+            self.check_quorums(chain, vote.height, &vote.block_hash, outgoing);
+            pending.extend(outgoing[before..].iter().cloned());
+        }
+    }
+
+    /// Casts a precommit on a prevote quorum and finalizes the block on a precommit quorum.
+    fn check_quorums(&mut self, chain: &mut Blockchain, height: usize, block_hash: &str, outgoing: &mut Vec<Vote>) {
+        let in_chain = chain.get_blocks().get(height).map(|b| b.get_hash()).as_deref() == Some(block_hash);
+        if !in_chain {
+            return;
+        }
+
+        if self.has_quorum(chain, VoteKind::Prevote, height, block_hash) {
+            if let Some(vote) = self.own_vote(chain, VoteKind::Precommit, height, block_hash.to_string()) {
+                outgoing.push(vote);
+            }
+        }
+
+        if self.has_quorum(chain, VoteKind::Precommit, height, block_hash) && chain.finalize(height) {
+            println!("Block {} at height {} is final.", block_hash, height);
+            self.votes.retain(|(h, _), _| *h > height);
+            self.cast.retain(|(h, _, _), _| *h > height);
+            self.voted.retain(|(_, h)| *h > height);
+        }
+    }
+
+    /// Returns `true` if votes of `kind` for the block exceed 2/3 of the voting weight.
+    fn has_quorum(&self, chain: &Blockchain, kind: VoteKind, height: usize, block_hash: &str) -> bool {
+        // Stakes are summed in u128, so large stakes cannot overflow the comparison.
+        let weights = consensus::voting_weights(chain);
+        let total: u128 = weights.values().map(|weight| *weight as u128).sum();
+        let voted: u128 = self.votes
+            .get(&(height, block_hash.to_string()))
+            .and_then(|rounds| rounds.get(&kind))
+            .map(|voters| voters.iter().filter_map(|v| weights.get(v)).map(|weight| *weight as u128).sum())
+            .unwrap_or(0);
+        total > 0 && voted * 3 > total * 2
+    }
+
+    /// Signs a local vote if the node is a validator that has not voted in this round and height yet.
+    fn own_vote(&mut self, chain: &Blockchain, kind: VoteKind, height: usize, block_hash: String) -> Option<Vote> {
+        let local = crypto::encode_public_key(&self.keypair)?;
+        if !consensus::voting_weights(chain).contains_key(&local) || !self.voted.insert((kind, height)) {
+            return None;
+        }
+        Vote::new_signed(&self.keypair, kind, height, block_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain_spec::{ChainSpec, ConsensusKind};
+
+    fn poa_chain(validators: &[&str]) -> Blockchain {
+        Blockchain::new(ChainSpec {
+            consensus: ConsensusKind::ProofOfAuthority { validators: validators.iter().map(|v| v.to_string()).collect() },
+            ..ChainSpec::default()
+        })
+    }
+
+    fn gadget() -> FinalityGadget {
+        FinalityGadget::new(identity::Keypair::generate_ed25519())
+    }
+
+    fn record(gadget: &mut FinalityGadget, kind: VoteKind, voters: &[&str]) {
+        gadget.votes
+            .entry((1, "hash".to_string()))
+            .or_default()
+            .entry(kind)
+            .or_default()
+            .extend(voters.iter().map(|v| v.to_string()));
+    }
+
+    #[test]
+    fn two_of_three_validators_are_not_a_quorum() {
+        let chain = poa_chain(&["a", "b", "c"]);
+        let mut gadget = gadget();
+        record(&mut gadget, VoteKind::Prevote, &["a", "b"]);
+        assert!(!gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+        record(&mut gadget, VoteKind::Prevote, &["c"]);
+        assert!(gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+    }
+
+    #[test]
+    fn three_of_four_validators_are_a_quorum() {
+        let chain = poa_chain(&["a", "b", "c", "d"]);
+        let mut gadget = gadget();
+        record(&mut gadget, VoteKind::Precommit, &["a", "b", "c"]);
+        assert!(gadget.has_quorum(&chain, VoteKind::Precommit, 1, "hash"));
+        assert!(!gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+        assert!(!gadget.has_quorum(&chain, VoteKind::Precommit, 1, "other"));
+    }
+
+    #[test]
+    fn votes_of_non_validators_do_not_count() {
+        let chain = poa_chain(&["a", "b", "c"]);
+        let mut gadget = gadget();
+        record(&mut gadget, VoteKind::Prevote, &["a", "b", "x", "y"]);
+        assert!(!gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+    }
+
+    #[test]
+    fn open_chain_never_reaches_a_quorum() {
+        let chain = Blockchain::new(ChainSpec::default());
+        let mut gadget = gadget();
+        record(&mut gadget, VoteKind::Prevote, &["a"]);
+        assert!(!gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+    }
+
+    #[test]
+    fn large_stakes_do_not_overflow_the_quorum() {
+        let stakes = ["a", "b", "c"].iter().map(|v| (v.to_string(), u64::MAX)).collect();
+        let chain = Blockchain::new(ChainSpec {
+            consensus: ConsensusKind::ProofOfStake { genesis_stakes: stakes },
+            ..ChainSpec::default()
+        });
+        let mut gadget = gadget();
+        record(&mut gadget, VoteKind::Prevote, &["a", "b"]);
+        assert!(!gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+        record(&mut gadget, VoteKind::Prevote, &["c"]);
+        assert!(gadget.has_quorum(&chain, VoteKind::Prevote, 1, "hash"));
+    }
+
+    #[test]
+    fn second_vote_for_another_block_is_equivocation() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let voter = crypto::encode_public_key(&keypair).unwrap();
+        let mut chain = poa_chain(&[&voter, "b", "c"]);
+        let mut gadget = gadget();
+
+        let vote = Vote::new_signed(&keypair, VoteKind::Prevote, 1, "first".to_string()).unwrap();
+        assert!(gadget.on_vote(&mut chain, vote.clone()).is_ok());
+        assert!(gadget.on_vote(&mut chain, vote).is_ok());
+
+        let conflicting = Vote::new_signed(&keypair, VoteKind::Prevote, 1, "second".to_string()).unwrap();
+        assert!(gadget.on_vote(&mut chain, conflicting).is_err());
+
+        let precommit = Vote::new_signed(&keypair, VoteKind::Precommit, 1, "second".to_string()).unwrap();
+        assert!(gadget.on_vote(&mut chain, precommit).is_ok());
+    }
+
+    #[test]
+    fn badly_signed_vote_is_rejected() {
+        let keypair = identity::Keypair::generate_ed25519();
+        let voter = crypto::encode_public_key(&keypair).unwrap();
+        let mut chain = poa_chain(&[&voter]);
+        let mut vote = Vote::new_signed(&keypair, VoteKind::Prevote, 1, "first".to_string()).unwrap();
+        vote.block_hash = "second".to_string();
+        assert!(gadget().on_vote(&mut chain, vote).is_err());
+    }
+}
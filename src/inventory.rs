@@ -0,0 +1,58 @@
+//! This module implements the **seen-hash cache** of block announcements.
+//!
+//! Blocks are announced over GossipSub by hash and height only, and fetched from the
+//! announcing peer. The cache remembers the hashes already fetched or being fetched,
+//! so a block announced by several peers is downloaded once.
+
+use std::collections::{HashSet, VecDeque};
+
+/// Number of hashes the cache remembers before forgetting the oldest ones.
+const SEEN_CACHE_SIZE: usize = 1024;
+
+/// A bounded set of recently seen block hashes.
+#[derive(Debug)]
+pub struct SeenCache {
+    /// The remembered hashes.
+    hashes: HashSet<String>,
+
+    /// The remembered hashes, oldest first.
+    order: VecDeque<String>,
+}
+
+impl Default for SeenCache {
+    fn default() -> Self {
+        SeenCache {
+            hashes: HashSet::with_capacity(SEEN_CACHE_SIZE),
+            order: VecDeque::with_capacity(SEEN_CACHE_SIZE),
+        }
+    }
+}
+
+impl SeenCache {
+    /// Remembers a hash, forgetting the oldest one if the cache is full.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the hash is new.
+    /// - `false` if it was already seen.
+    pub fn insert(&mut self, hash: &str) -> bool {
+        if self.hashes.contains(hash) {
+            return false;
+        }
+        if self.order.len() == SEEN_CACHE_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.hashes.remove(&oldest);
+            }
+        }
+        self.hashes.insert(hash.to_string());
+        self.order.push_back(hash.to_string());
+        true
+    }
+
+    /// Forgets a hash, so the block can be fetched again after a failed download.
+    pub fn remove(&mut self, hash: &str) {
+        if self.hashes.remove(hash) {
+            self.order.retain(|seen| seen != hash);
+        }
+    }
+}
@@ -3,17 +3,101 @@
 //! It sets up the networking system, synchronizes the blockchain with peers,
 //! and provides a command-line interface for interacting with the local blockchain.
 
-use tokio::{io, io::AsyncBufReadExt, select, time::{timeout, Duration}};
+use tokio::{io, io::AsyncBufReadExt, select, time::{interval, timeout, Duration}};
 use futures::stream::StreamExt;
-use std::error::Error;
-use network::{init_network, NetworkMessage, broadcast_message, list_peers, handle_event, handle_mdns};
+use std::{collections::BTreeMap, error::Error, path::PathBuf};
+use clap::Parser;
+use libp2p::{identity, Multiaddr, PeerId, Swarm};
+use network::{init_network, CustomBehaviour, DEFAULT_LISTEN_ADDRS, NetworkMessage, Topics, broadcast_or_queue, broadcast_votes, flush_outbox, list_peers, handle_event, handle_events};
 use blockchain::*;
 use block::Block;
-use serde_json;
+use chain_spec::ChainSpec;
+use config::NodeConfig;
+use connections::ConnectionManager;
+use finality::FinalityGadget;
+use mempool::Mempool;
+use node::Node;
+use outbox::Outbox;
+use peer_manager::PeerManager;
+use peer_tracker::PeerTracker;
+use rate_limit::RateLimiter;
+use sync::Syncer;
+use transaction::{Transaction, TransactionKind};
 
+mod allowlist;
 mod block;
 mod blockchain;
+mod chain_spec;
+mod clock;
+mod config;
+mod connections;
+mod consensus;
+mod crypto;
+mod discovery;
+mod finality;
+mod inventory;
+mod mempool;
 mod network;
+mod node;
+mod outbox;
+mod peer_manager;
+mod peer_tracker;
+mod rate_limit;
+mod state;
+mod sync;
+mod transaction;
+mod wire;
+
+/// Command-line options of the blockchain node.
+#[derive(Parser, Debug)]
+#[command(about = "P2P blockchain node")]
+struct Cli {
+    /// Path to a JSON chain spec (chain id, genesis and consensus rules).
+    #[arg(long)]
+    chain_spec: Option<PathBuf>,
+
+    /// Path to the node's identity key file, created on first run.
+    #[arg(long, default_value = "node.key")]
+    key_file: PathBuf,
+
+    /// Path to a JSON node configuration (bootstrap peers, listen addresses, rate limits).
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Peer to dial at startup and stay connected to, as a multiaddr ending in `/p2p/<peer id>`.
+    /// May be repeated; adds to the bootstrap peers of the node configuration.
+    #[arg(long = "bootstrap", value_name = "MULTIADDR")]
+    bootstrap: Vec<Multiaddr>,
+
+    /// Address to listen on, e.g. `/ip4/0.0.0.0/tcp/4001`, `/ip4/0.0.0.0/udp/4001/quic-v1`
+    /// or `/ip4/0.0.0.0/tcp/4002/ws`. May be repeated; adds to the listen addresses of the
+    /// node configuration. Defaults to random TCP, QUIC and WebSocket ports.
+    #[arg(long = "listen", value_name = "MULTIADDR")]
+    listen: Vec<Multiaddr>,
+
+    /// Path to the file the DHT routing table is persisted to.
+    #[arg(long, default_value = "routing_table.json")]
+    routing_table: PathBuf,
+
+    /// Path to the file banned peers are persisted to.
+    #[arg(long, default_value = "bans.json")]
+    ban_list: PathBuf,
+
+    /// Peer allowed to connect, as a peer id. May be repeated; adds to the allowlist of
+    /// the node configuration. When any peer is listed, all others are refused.
+    #[arg(long = "allow", value_name = "PEER_ID")]
+    allow: Vec<PeerId>,
+
+    /// Path to a swarm key file. Runs the node in a private network that only
+    /// accepts connections from nodes with the same key, over TCP.
+    #[arg(long)]
+    swarm_key: Option<PathBuf>,
+
+    /// Runs as a light node, subscribed only to block announcements: the node does not
+    /// receive gossiped transactions and finality votes. It still downloads full blocks.
+    #[arg(long)]
+    light: bool,
+}
 
 /// **Main entry point** for the P2P blockchain node.
 ///
@@ -35,11 +119,84 @@ mod network;
 /// This will start a blockchain node that can communicate with other peers.
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    // Initialize the network swarm and topic for message broadcasting.
-    let (mut swarm, topic) = init_network()?;
+    let cli = Cli::parse();
+
+    // Load the chain spec, falling back to an open chain when none is given.
+    let spec = match &cli.chain_spec {
+        Some(path) => ChainSpec::load(path)?,
+        None => ChainSpec::default(),
+    };
+
+    let config = match &cli.config {
+        Some(path) => NodeConfig::load(path)?,
+        None => NodeConfig::default(),
+    };
+
+    // The node identity is kept across restarts, so the node's PeerId stays stable.
+    // It also signs the blocks and transactions this node produces.
+    let local_key = crypto::load_or_create_keypair(&cli.key_file)?;
+    let local_public_key = crypto::encode_public_key(&local_key).expect("Node key is an Ed25519 key");
+    println!("Node public key: {}", local_public_key);
+    println!("Node peer id: {}", PeerId::from(local_key.public()));
+
+    // In a private network, connections only succeed between nodes sharing the swarm key.
+    let swarm_key = match &cli.swarm_key {
+        Some(path) => Some(crypto::load_swarm_key(path)?),
+        None => None,
+    };
+    if let Some(swarm_key) = &swarm_key {
+        println!("Private network mode, swarm key fingerprint {}", swarm_key.fingerprint());
+    }
+
+    // Initialize the network swarm and the topics of the chain for message broadcasting.
+    let mut listen_addrs = config.listen_addrs()?;
+    listen_addrs.extend(cli.listen.iter().cloned());
+    if listen_addrs.is_empty() {
+        for addr in DEFAULT_LISTEN_ADDRS {
+            listen_addrs.push(addr.parse()?);
+        }
+    }
+    // Only listed peers may connect once an allowlist is given.
+    let mut allowlist = config.allowed_peers()?;
+    allowlist.extend(cli.allow.iter().copied());
+    let allowlist = (!allowlist.is_empty()).then_some(allowlist);
+
+    let (mut swarm, topics) = init_network(local_key.clone(), &listen_addrs, swarm_key, allowlist, &spec.chain_id, cli.light).await?;
+
+    // Rejoin the DHT through the peers known from the last run and the configured bootstrap peers.
+    match discovery::load_routing_table(&mut swarm, &cli.routing_table) {
+        Ok(restored) if restored > 0 => println!("Restored {} peers from the DHT routing table.", restored),
+        Ok(_) => {}
+        Err(e) => println!("Failed to load the DHT routing table: {}", e),
+    }
 
-    // Local blockchain instance (starts fresh unless synchronized with peers).
-    let mut local_blockchain = Blockchain::new();
+    // Dial the bootstrap peers and keep reconnecting to them when connections drop.
+    let mut connections = ConnectionManager::new();
+    let mut bootstrap_peers = config.bootstrap_addrs()?;
+    bootstrap_peers.extend(cli.bootstrap.iter().cloned());
+    for addr in &bootstrap_peers {
+        if let Some((peer_id, transport)) = discovery::split_peer_addr(addr) {
+            connections.add_bootstrap_peer(peer_id, transport);
+        }
+    }
+    discovery::bootstrap(&mut swarm, &bootstrap_peers);
+    let mut random_walk = interval(discovery::RANDOM_WALK_INTERVAL);
+    let mut connection_ticks = interval(connections::TICK_INTERVAL);
+
+    // The local blockchain starts fresh unless synchronized with peers,
+    // with an empty mempool and no finality votes.
+    let mut node = Node {
+        blockchain: Blockchain::new(spec),
+        mempool: Mempool::new(),
+        outbox: Outbox::new(),
+        finality: FinalityGadget::new(local_key.clone()),
+        connections,
+        tracker: PeerTracker::new(),
+        peers: PeerManager::load(&mut swarm, &cli.ban_list)?,
+        rate_limits: RateLimiter::new(config.rate_limits.clone()),
+        sync: Syncer::new(),
+        swarm_key,
+    };
     
     // Input reader for command-line interactions.
     let mut stdin = io::BufReader::new(io::stdin()).lines();
@@ -50,27 +207,28 @@ async fn main() -> Result<(), Box<dyn Error>> {
     println!("Node active.");
     println!("Initializing mDNS discovery...");
 
-    // Attempt to discover peers within the sync timeout window.
-    let sync_result = timeout(sync_timeout, handle_mdns(&mut swarm)).await;
+    // Attempt to discover peers within the sync timeout window. Network events are
+    // handled as usual, so peers connecting meanwhile are tracked and synchronized with.
+    let sync_result = timeout(sync_timeout, handle_events(&mut swarm, &topics, &mut node)).await;
     
     match sync_result {
         Ok(_) => println!("Initialization successful."),
         Err(_) => println!("Initialization failed."),
     }
 
-    // Request blockchain data from peers to avoid duplicate genesis blocks.
-    broadcast_message(&mut swarm, &topic, NetworkMessage::ChainRequest);
-
     // Command-line interface (CLI) loop for user interaction.
+    // The menu is printed again after each command, not after background events.
+    let mut show_menu = true;
     loop {
-        println!("\nOption menu:\n");
-        println!("> Add Block (adds new block to blockchain)");
-        println!("> List Peers (lists all active peers connected to the p2p network)");
-        println!("> List Blockchain (prints the blocks of the local blockchain)\n");
+        if show_menu {
+            print_menu();
+            show_menu = false;
+        }
 
         select! {
             // Read user input from the command line.
             Ok(Some(line)) = stdin.next_line() => {
+                show_menu = true;
                 match line.as_str() {
                     
                     // Command to add a new block.
@@ -79,39 +237,177 @@ async fn main() -> Result<(), Box<dyn Error>> {
                         if !data.is_empty() {
                             
                             // Retrieve the last block in the local blockchain.
-                            let prev_block = local_blockchain.get_last_block().unwrap();
-                            
-                            // Create a new block with incremented height.
-                            let new_block = Block::new_block(
-                                prev_block.get_hash().to_string(),
-                                prev_block.get_height() + 1,
-                            );
-
-                            // Announce the new block to the P2P network.
-                            let serialized_block = serde_json::to_string(&new_block).unwrap();
-                            broadcast_message(&mut swarm, &topic, NetworkMessage::NewBlock(serialized_block));
+                            let prev_block = node.blockchain.get_last_block().unwrap();
                             
-                            // Add the new block to the local blockchain.
-                            local_blockchain.add_block(new_block);
-                            println!("Block added and broadcasted to P2P network: {}", data);
+                            let height = prev_block.get_height() + 1;
+                            let prev_hash = prev_block.get_hash();
+
+                            // Under proof-of-authority and proof-of-stake only the validator in turn may produce the block.
+                            let expected = consensus::next_producer(&node.blockchain);
+                            if let Some(expected) = &expected {
+                                if *expected != local_public_key {
+                                    println!("Not this node's turn to produce block {} (expected validator {}).", height, expected);
+                                    continue;
+                                }
+                            }
+
+                            // Store the data in a transaction and include every pending transaction that still applies.
+                            let nonce = node.mempool.next_nonce(&local_public_key, node.blockchain.get_state());
+                            if let Some(tx) = Transaction::new_signed(&local_key, nonce, TransactionKind::Data(data.to_string())) {
+                                node.mempool.add(tx);
+                            }
+                            let transactions = node.mempool.select_for_block(node.blockchain.get_state());
+
+                            // Create a new block with incremented height, timestamped with the network-adjusted time and signed by this node.
+                            let mut new_block = Block::new_block(prev_hash, height, node.blockchain.next_timestamp(), transactions);
+                            new_block.sign(&local_key);
+
+                            // Add the new block to the local blockchain, then announce it to the P2P network.
+                            let announcement = NetworkMessage::NewBlock { hash: new_block.get_hash(), height };
+                            if node.blockchain.add_block(new_block.clone()) {
+                                node.mempool.remove_included(&new_block, node.blockchain.get_state());
+                                broadcast_or_queue(&mut swarm, &topics, &mut node.outbox, announcement);
+                                println!("Block added and broadcasted to P2P network: {}", data);
+
+                                let votes = node.finality.on_block_added(&mut node.blockchain, height);
+                                broadcast_votes(&mut swarm, &topics, &mut node.outbox, votes);
+                            }
                         }
                     }
 
+                    // Command to bond stake.
+                    cmd if cmd.starts_with("Bond") => {
+                        match cmd.strip_prefix("Bond").unwrap_or("").trim().parse::<u64>() {
+                            Ok(amount) => submit_transaction(TransactionKind::Bond(amount), &local_key, &node.blockchain, &mut node.mempool, &mut node.outbox, &mut swarm, &topics),
+                            Err(_) => println!("Usage: Bond <amount>"),
+                        }
+                    }
+
+                    // Command to unbond stake.
+                    cmd if cmd.starts_with("Unbond") => {
+                        match cmd.strip_prefix("Unbond").unwrap_or("").trim().parse::<u64>() {
+                            Ok(amount) => submit_transaction(TransactionKind::Unbond(amount), &local_key, &node.blockchain, &mut node.mempool, &mut node.outbox, &mut swarm, &topics),
+                            Err(_) => println!("Usage: Unbond <amount>"),
+                        }
+                    }
+
+                    // Command to dial a peer.
+                    cmd if cmd.starts_with("Connect") => {
+                        match cmd.strip_prefix("Connect").unwrap_or("").trim().parse::<Multiaddr>() {
+                            Ok(addr) => node.connections.connect(&mut swarm, addr),
+                            Err(_) => println!("Usage: Connect <multiaddr>"),
+                        }
+                    }
+
+                    // Command to close the connections to a peer.
+                    cmd if cmd.starts_with("Disconnect") => {
+                        match cmd.strip_prefix("Disconnect").unwrap_or("").trim().parse::<PeerId>() {
+                            Ok(peer_id) => node.connections.disconnect(&mut swarm, peer_id),
+                            Err(_) => println!("Usage: Disconnect <peer id>"),
+                        }
+                    }
+
+                    // Command to ban a peer for some time.
+                    cmd if cmd.starts_with("Ban") => {
+                        let args: Vec<&str> = cmd.split_whitespace().skip(1).collect();
+                        let peer_id = args.first().and_then(|arg| arg.parse::<PeerId>().ok());
+                        let duration = args.get(1).map(|arg| connections::parse_duration(arg));
+                        match (peer_id, duration) {
+                            (Some(peer_id), Some(Ok(duration))) => node.peers.ban(&mut swarm, peer_id, duration, "banned from the command line"),
+                            (Some(_), Some(Err(e))) => println!("Invalid ban duration: {}", e),
+                            _ => println!("Usage: Ban <peer id> <duration, e.g. 30s, 10m, 2h>"),
+                        }
+                    }
+
+                    // Command to lift the ban of a peer.
+                    cmd if cmd.starts_with("Unban") => {
+                        match cmd.strip_prefix("Unban").unwrap_or("").trim().parse::<PeerId>() {
+                            Ok(peer_id) if node.peers.unban(&mut swarm, peer_id) => println!("Unbanned {}.", peer_id),
+                            Ok(peer_id) => println!("{} is not banned.", peer_id),
+                            Err(_) => println!("Usage: Unban <peer id>"),
+                        }
+                    }
+
+                    // Command to display the banned peers.
+                    cmd if cmd.starts_with("List Bans") => {
+                        node.peers.print_bans();
+                    }
+
+                    // Command to add a peer to the allowlist.
+                    cmd if cmd.starts_with("Allow") => {
+                        match cmd.strip_prefix("Allow").unwrap_or("").trim().parse::<PeerId>() {
+                            Ok(peer_id) => node.connections.allow(&mut swarm, peer_id),
+                            Err(_) => println!("Usage: Allow <peer id>"),
+                        }
+                    }
+
+                    // Command to remove a peer from the allowlist.
+                    cmd if cmd.starts_with("Disallow") => {
+                        match cmd.strip_prefix("Disallow").unwrap_or("").trim().parse::<PeerId>() {
+                            Ok(peer_id) => node.connections.disallow(&mut swarm, peer_id),
+                            Err(_) => println!("Usage: Disallow <peer id>"),
+                        }
+                    }
+
+                    // Command to display the allowlist.
+                    cmd if cmd.starts_with("List Allowed") => {
+                        node.connections.print_allowlist(&swarm);
+                    }
+
+                    // Command to display the number of blocks produced per peer.
+                    cmd if cmd.starts_with("List Producers") => {
+                        let mut produced: BTreeMap<String, usize> = BTreeMap::new();
+                        for block in node.blockchain.get_blocks().iter().skip(1) {
+                            let producer = block.producer.as_deref()
+                                .and_then(crypto::peer_id_of)
+                                .map(|peer_id| peer_id.to_string())
+                                .unwrap_or_else(|| "unknown".to_string());
+                            *produced.entry(producer).or_insert(0) += 1;
+                        }
+
+                        println!("\nBlocks produced per peer:");
+                        for (peer_id, count) in produced {
+                            println!("{}: {}", peer_id, count);
+                        }
+                    }
+
+                    // Command to display the bonded stakes.
+                    cmd if cmd.starts_with("List Stakes") => {
+                        let state = node.blockchain.get_state();
+                        println!("\nBonded stakes:");
+                        for (validator, stake) in state.get_stakes() {
+                            println!("{}: {}", validator, stake);
+                        }
+                        println!("\nThis node: balance {}, stake {}", state.balance(&local_public_key), state.stake(&local_public_key));
+                    }
+
+                    // Command to display the progress of the chain synchronization.
+                    cmd if cmd.starts_with("Sync Status") => {
+                        node.sync.print_status(&node.blockchain);
+                    }
+
                     // Command to list active peers.
                     cmd if cmd.starts_with("List Peers") => {
-                        list_peers(&mut swarm);
+                        list_peers(&mut swarm, &topics, &node);
                     }
 
                     // Command to display the blockchain.
                     cmd if cmd.starts_with("List Blockchain") => {
                         println!("\nCurrent Blockchain:");
-                        for block in local_blockchain.get_blocks() {
+                        for block in node.blockchain.get_blocks() {
                             println!("---------------------------");
                             println!("Timestamp: {}", block.get_timestamp());
                             println!("Previous Block Hash: {}", block.get_prev_hash());
                             println!("Current Block Hash: {}", block.get_hash());
                             println!("Height: {}", block.get_height());
+                            if let Some(peer_id) = block.producer.as_deref().and_then(crypto::peer_id_of) {
+                                println!("Producer: {}", peer_id);
+                            }
+                            println!("Transactions: {}", block.transactions.len());
+                            println!("Final: {}", block.get_height() <= node.blockchain.finalized_height());
                         }
+                        println!("---------------------------");
+                        println!("Finalized height: {}", node.blockchain.finalized_height());
                     }
 
                     // Handle unknown commands.
@@ -119,8 +415,86 @@ async fn main() -> Result<(), Box<dyn Error>> {
                 }
             }
 
+            // Periodically explore the DHT and persist the routing table.
+            _ = random_walk.tick() => {
+                discovery::random_walk(&mut swarm);
+                if let Err(e) = discovery::save_routing_table(&mut swarm, &cli.routing_table) {
+                    println!("Failed to save the DHT routing table: {}", e);
+                }
+            }
+
+            // Redial bootstrap peers, decay reputations, lift expired bans, drop refilled rate limit
+            // buckets and send the queued messages once a GossipSub mesh formed.
+            _ = connection_ticks.tick() => {
+                node.connections.tick(&mut swarm);
+                node.peers.tick(&mut swarm);
+                node.rate_limits.prune();
+                flush_outbox(&mut swarm, &topics, &mut node);
+            }
+
             // Process incoming network events (e.g., new blocks, peer messages).
-            event = swarm.select_next_some() => handle_event(event, &mut swarm, &topic, &mut local_blockchain),
+            event = swarm.select_next_some() => handle_event(event, &mut swarm, &topics, &mut node),
         }
     }
 }
+
+/// Prints the command menu.
+fn print_menu() {
+    println!("\nOption menu:\n");
+    println!("> Add Block (adds new block to blockchain)");
+    println!("> List Peers (lists all active peers connected to the p2p network)");
+    println!("> List Blockchain (prints the blocks of the local blockchain)");
+    println!("> Bond <amount> (moves balance into stake)");
+    println!("> Unbond <amount> (moves stake back into balance)");
+    println!("> List Stakes (prints the bonded stake of every validator)");
+    println!("> List Producers (prints the number of blocks produced per peer)");
+    println!("> Sync Status (prints the height, target and rate of the chain synchronization)");
+    println!("> Connect <multiaddr> (dials a peer)");
+    println!("> Disconnect <peer id> (closes the connections to a peer)");
+    println!("> Ban <peer id> <duration> (disconnects a peer and refuses it for a while)");
+    println!("> Unban <peer id> (lifts the ban of a peer)");
+    println!("> List Bans (prints the banned peers)");
+    println!("> Allow <peer id> (adds a peer to the allowlist)");
+    println!("> Disallow <peer id> (removes a peer from the allowlist and disconnects it)");
+    println!("> List Allowed (prints the allowlist)\n");
+}
+
+/// Signs a transaction with the node key, adds it to the mempool and announces it to the network.
+///
+/// # Arguments
+///
+/// * `kind` - The action performed by the transaction.
+/// * `local_key` - The node's keypair, used as the sender.
+/// * `local_blockchain` - The local blockchain, used to pick the next nonce.
+/// * `mempool` - The local pool of pending transactions.
+/// * `outbox` - The queue of messages waiting for a peer.
+/// * `swarm` - The network swarm instance.
+/// * `topics` - The GossipSub topics.
+fn submit_transaction(
+    kind: TransactionKind,
+    local_key: &identity::Keypair,
+    local_blockchain: &Blockchain,
+    mempool: &mut Mempool,
+    outbox: &mut Outbox,
+    swarm: &mut Swarm<CustomBehaviour>,
+    topics: &Topics,
+) {
+    let Some(sender) = crypto::encode_public_key(local_key) else {
+        return;
+    };
+    let nonce = mempool.next_nonce(&sender, local_blockchain.get_state());
+    let Some(tx) = Transaction::new_signed(local_key, nonce, kind) else {
+        println!("Failed to sign transaction.");
+        return;
+    };
+    if let Err(reason) = mempool.check(&tx, local_blockchain.get_state()) {
+        println!("Transaction rejected: {}", reason);
+        return;
+    }
+
+    let serialized_tx = serde_json::to_string(&tx).unwrap();
+    if mempool.add(tx) {
+        broadcast_or_queue(swarm, topics, outbox, NetworkMessage::NewTransaction(serialized_tx));
+        println!("Transaction added to the mempool.");
+    }
+}
@@ -0,0 +1,179 @@
+//! This module defines the **mempool**, the set of transactions waiting to be included in a block.
+//!
+//! The mempool holds at most [`MAX_TRANSACTIONS`]. Once it is full, the oldest
+//! transaction makes room for a new one, together with the later transactions of the
+//! same sender, which can no longer apply without it.
+
+use std::collections::HashSet;
+
+use crate::block::Block;
+use crate::state::ChainState;
+use crate::transaction::Transaction;
+
+/// Maximum number of pending transactions.
+const MAX_TRANSACTIONS: usize = 5000;
+
+/// Pending transactions, kept in arrival order.
+#[derive(Debug, Default)]
+pub struct Mempool {
+    /// The pending transactions.
+    transactions: Vec<Transaction>,
+
+    /// The ids of the pending transactions.
+    ids: HashSet<String>,
+}
+
+impl Mempool {
+    /// Creates an empty mempool.
+    pub fn new() -> Self {
+        Mempool::default()
+    }
+
+    /// Adds a transaction to the mempool.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the transaction was added, evicting the oldest ones if the mempool is full.
+    /// - `false` if it is already pending or badly signed.
+    pub fn add(&mut self, tx: Transaction) -> bool {
+        if !tx.verify_signature() {
+            return false;
+        }
+
+        let id = tx.id();
+        if self.ids.contains(&id) {
+            return false;
+        }
+
+        if self.transactions.len() >= MAX_TRANSACTIONS {
+            self.evict_oldest();
+        }
+        self.ids.insert(id);
+        self.transactions.push(tx);
+        true
+    }
+
+    /// Returns whether a transaction is pending.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The id of the transaction.
+    pub fn contains(&self, id: &str) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Returns the pending transactions, in arrival order.
+    pub fn transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    /// Returns the nonce for the next transaction of `account`,
+    /// accounting for its transactions that are still pending.
+    ///
+    /// # Arguments
+    ///
+    /// * `account` - The hex-encoded public key of the sender.
+    /// * `state` - The current chain state.
+    pub fn next_nonce(&self, account: &str, state: &ChainState) -> u64 {
+        let pending = self.transactions.iter().filter(|tx| tx.sender == account).count() as u64;
+        state.nonce(account) + pending
+    }
+
+    /// Selects the pending transactions that apply cleanly on top of `state`, in order.
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The state the next block will be applied to.
+    pub fn select_for_block(&self, state: &ChainState) -> Vec<Transaction> {
+        let mut state = state.clone();
+        self.transactions
+            .iter()
+            .filter(|tx| state.apply(tx).is_ok())
+            .cloned()
+            .collect()
+    }
+
+    /// Checks that `tx` applies cleanly after the pending transactions selected for the next block.
+    ///
+    /// A transaction only changes the balance, stake and nonce of its sender, so only
+    /// the sender's pending transactions are applied first.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the transaction would be rejected by the chain state.
+    pub fn check(&self, tx: &Transaction, state: &ChainState) -> Result<(), String> {
+        let mut state = state.clone();
+        for pending in self.transactions.iter().filter(|pending| pending.sender == tx.sender) {
+            let _ = state.apply(pending);
+        }
+        state.apply(tx)
+    }
+
+    /// Puts back the transactions of blocks dropped by a reorganization.
+    ///
+    /// The transactions go ahead of the pending ones, which were sent after them. Those
+    /// that no longer apply on top of `state`, e.g. because the new chain includes them
+    /// too, are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `blocks` - The orphaned blocks, in chain order.
+    /// * `state` - The chain state after the reorganization.
+    ///
+    /// # Returns
+    ///
+    /// The number of transactions put back.
+    pub fn restore(&mut self, blocks: &[Block], state: &ChainState) -> usize {
+        let mut state = state.clone();
+        let restored: Vec<Transaction> = blocks.iter()
+            .flat_map(|block| &block.transactions)
+            .filter(|tx| state.apply(tx).is_ok() && !self.ids.contains(&tx.id()))
+            .cloned()
+            .collect();
+
+        let count = restored.len();
+        self.ids.extend(restored.iter().map(Transaction::id));
+        self.transactions.splice(0..0, restored);
+        while self.transactions.len() > MAX_TRANSACTIONS {
+            self.evict_oldest();
+        }
+        count
+    }
+
+    /// Removes the transactions included in `block`, as well as those it made stale.
+    ///
+    /// # Arguments
+    ///
+    /// * `block` - The block appended to the blockchain.
+    /// * `state` - The chain state after applying the block.
+    pub fn remove_included(&mut self, block: &Block, state: &ChainState) {
+        let included: HashSet<String> = block.transactions.iter().map(|tx| tx.id()).collect();
+        let ids = &mut self.ids;
+        self.transactions.retain(|tx| {
+            let id = tx.id();
+            let keep = !included.contains(&id) && tx.nonce >= state.nonce(&tx.sender);
+            if !keep {
+                ids.remove(&id);
+            }
+            keep
+        });
+    }
+
+    /// Drops the oldest transaction and the later transactions of its sender.
+    fn evict_oldest(&mut self) {
+        let Some(oldest) = self.transactions.first().cloned() else {
+            return;
+        };
+        let ids = &mut self.ids;
+        let mut evicted = 0;
+        self.transactions.retain(|tx| {
+            let keep = tx.sender != oldest.sender || tx.nonce < oldest.nonce;
+            if !keep {
+                ids.remove(&tx.id());
+                evicted += 1;
+            }
+            keep
+        });
+        println!("Mempool full: evicted {} transactions of {}.", evicted, oldest.sender);
+    }
+}
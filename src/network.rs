@@ -3,31 +3,64 @@
 //! It defines network behaviors, message types, and peer discovery mechanisms using **libp2p**.
 //! The module supports:
 //! - **GossipSub** for decentralized message broadcasting
-//! - **mDNS** for peer discovery
-//! - **Handling blockchain synchronization requests and responses**
+//! - **mDNS** for local peer discovery
+//! - **Kademlia** for peer discovery beyond the local network (see [`crate::discovery`])
+//! - **Request-response** for blockchain synchronization with a single peer (see [`crate::sync`])
+//! - **Ping** and **Identify** for connection diagnostics (see [`crate::peer_tracker`])
+//!
+//! Connections run over TCP, QUIC or WebSocket, and `/dns4/` and `/dns6/` addresses are resolved when dialing.
+//! In **private network** mode, connections only run over TCP and start with a
+//! pre-shared key handshake, so nodes without the swarm key cannot connect. An
+//! optional **allowlist** further restricts connections to the listed peers.
 
 use std::{
-    collections::hash_map::DefaultHasher,
+    convert::Infallible,
     error::Error,
-    hash::{Hash, Hasher},
     time::Duration,
 };
 
 use libp2p::{
-    gossipsub, mdns, noise,
+    allow_block_list, gossipsub, identify, kad, mdns, noise, ping, request_response,
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade::Version},
+    multiaddr::Protocol,
+    pnet::{PnetConfig, PreSharedKey},
     swarm::{NetworkBehaviour, Swarm, SwarmEvent},
     tcp, yamux,
-    identity, PeerId,
+    identity, Multiaddr, PeerId, Transport,
 };
 use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
 use tracing_subscriber::EnvFilter;
 use std::collections::HashSet;
 use futures::StreamExt;
 
-use crate::blockchain::Blockchain;
-use crate::block::Block;
+use crate::allowlist::Allowlist;
+use crate::consensus;
+use crate::discovery;
+use crate::finality::{self, Vote};
+use crate::node::Node;
+use crate::outbox::Outbox;
+use crate::peer_manager::Misbehaviour;
+use crate::rate_limit::MessageKind;
+use crate::sync::{self, SyncRequest, SyncResponse};
+use crate::transaction::Transaction;
+use crate::wire::{self, Decoded};
+
+/// Addresses the node listens on when none are configured: random TCP, QUIC and WebSocket ports.
+pub const DEFAULT_LISTEN_ADDRS: [&str; 3] = [
+    "/ip4/0.0.0.0/tcp/0",
+    "/ip4/0.0.0.0/udp/0/quic-v1",
+    "/ip4/0.0.0.0/tcp/0/ws",
+];
+
+/// How long the ids of seen GossipSub messages are remembered. A message received again
+/// within the window is dropped as a duplicate; after it, the message is processed again.
+const DEDUP_WINDOW: Duration = Duration::from_secs(120);
+
+/// Protocol version exchanged through the identify protocol.
+const IDENTIFY_PROTOCOL: &str = "/p2p_blockchain/id/1.0.0";
 
-/// Defines the custom network behavior by combining **GossipSub** and **mDNS** for peer discovery.
+/// Defines the custom network behavior by combining **GossipSub** with **mDNS** and **Kademlia** for peer discovery.
 #[derive(NetworkBehaviour)]
 #[behaviour(out_event = "CustomBehaviourEvent")]
 pub struct CustomBehaviour {
@@ -36,16 +69,55 @@ pub struct CustomBehaviour {
 
     /// mDNS for local peer discovery.
     pub mdns: mdns::tokio::Behaviour,
+
+    /// Kademlia DHT for peer discovery beyond the local network.
+    pub kad: kad::Behaviour<kad::store::MemoryStore>,
+
+    /// Request-response protocol fetching headers and blocks from a single peer.
+    pub sync: sync::SyncBehaviour,
+
+    /// Ping protocol measuring the round-trip time to connected peers.
+    pub ping: ping::Behaviour,
+
+    /// Identify protocol exchanging agent versions and listen addresses with connected peers.
+    pub identify: identify::Behaviour,
+
+    /// Peers banned from connecting to the node.
+    pub blocked: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+
+    /// The only peers allowed to connect to the node, once the allowlist is enabled.
+    pub allowed: Allowlist,
+}
+
+impl CustomBehaviour {
+    /// Returns whether a peer may connect to the node: any peer while the allowlist is
+    /// disabled, otherwise only the listed ones.
+    pub fn is_allowed(&self, peer_id: &PeerId) -> bool {
+        self.allowed.is_allowed(peer_id)
+    }
 }
 
 /// Represents the events emitted by the custom network behavior.
 #[derive(Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum CustomBehaviourEvent {
     /// Event triggered by the GossipSub protocol.
     GossipSub(gossipsub::Event),
 
     /// Event triggered by the mDNS protocol.
     Mdns(mdns::Event),
+
+    /// Event triggered by the Kademlia DHT.
+    Kademlia(kad::Event),
+
+    /// Event triggered by the sync protocol.
+    Sync(request_response::Event<SyncRequest, SyncResponse>),
+
+    /// Event triggered by the ping protocol.
+    Ping(ping::Event),
+
+    /// Event triggered by the identify protocol.
+    Identify(identify::Event),
 }
 
 impl From<gossipsub::Event> for CustomBehaviourEvent {
@@ -60,54 +132,158 @@ impl From<mdns::Event> for CustomBehaviourEvent {
     }
 }
 
+impl From<kad::Event> for CustomBehaviourEvent {
+    fn from(event: kad::Event) -> Self {
+        CustomBehaviourEvent::Kademlia(event)
+    }
+}
+
+impl From<request_response::Event<SyncRequest, SyncResponse>> for CustomBehaviourEvent {
+    fn from(event: request_response::Event<SyncRequest, SyncResponse>) -> Self {
+        CustomBehaviourEvent::Sync(event)
+    }
+}
+
+impl From<ping::Event> for CustomBehaviourEvent {
+    fn from(event: ping::Event) -> Self {
+        CustomBehaviourEvent::Ping(event)
+    }
+}
+
+impl From<identify::Event> for CustomBehaviourEvent {
+    fn from(event: identify::Event) -> Self {
+        CustomBehaviourEvent::Identify(event)
+    }
+}
+
+impl From<Infallible> for CustomBehaviourEvent {
+    fn from(event: Infallible) -> Self {
+        match event {}
+    }
+}
+
 /// Defines the types of messages exchanged between peers in the network.
-#[derive(Serialize, Deserialize, Debug)]
+///
+/// Messages are sent in a versioned envelope, with the snake case name of the variant
+/// as type tag (see [`crate::wire`]).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
 pub enum NetworkMessage {
-    /// Announces a new block to the network.
-    NewBlock(String),
+    /// Announces a new block by hash and height. Peers missing it fetch it from the
+    /// announcing peer with a `GetBlocks` sync request.
+    NewBlock { hash: String, height: usize },
+
+    /// Announces a new transaction waiting to be included in a block.
+    NewTransaction(String),
 
-    /// Requests the current blockchain state from peers.
-    ChainRequest,
+    /// Carries a validator's signed finality vote.
+    Vote(String),
+}
 
-    /// Responds to a `ChainRequest` with the serialized blockchain.
-    ChainResponse(Vec<String>),
+impl NetworkMessage {
+    /// Returns the rate-limited kind of the message.
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            NetworkMessage::NewBlock { .. } => MessageKind::NewBlock,
+            NetworkMessage::NewTransaction(_) => MessageKind::NewTransaction,
+            NetworkMessage::Vote(_) => MessageKind::Vote,
+        }
+    }
 }
 
-/// Initializes the P2P network, setting up **GossipSub** and **mDNS** for communication.
+/// The GossipSub topics of a chain, one per kind of message.
+///
+/// Topic names are prefixed with the chain id, so nodes of different chains sharing
+/// a network do not receive each other's messages.
+#[derive(Debug, Clone)]
+pub struct Topics {
+    /// Block announcements.
+    pub blocks: gossipsub::IdentTopic,
+
+    /// Transactions waiting to be included in a block.
+    pub transactions: gossipsub::IdentTopic,
+
+    /// Finality votes.
+    pub votes: gossipsub::IdentTopic,
+}
+
+impl Topics {
+    /// Creates the topics of a chain: `<chain id>/blocks`, `<chain id>/transactions` and `<chain id>/votes`.
+    pub fn new(chain_id: &str) -> Self {
+        Topics {
+            blocks: gossipsub::IdentTopic::new(format!("{}/blocks", chain_id)),
+            transactions: gossipsub::IdentTopic::new(format!("{}/transactions", chain_id)),
+            votes: gossipsub::IdentTopic::new(format!("{}/votes", chain_id)),
+        }
+    }
+
+    /// Returns the topics with a short name for display.
+    pub fn named(&self) -> [(&'static str, &gossipsub::IdentTopic); 3] {
+        [("blocks", &self.blocks), ("transactions", &self.transactions), ("votes", &self.votes)]
+    }
+
+    /// Returns the topic a message is published on.
+    pub fn for_message(&self, msg: &NetworkMessage) -> &gossipsub::IdentTopic {
+        match msg {
+            NetworkMessage::NewBlock { .. } => &self.blocks,
+            NetworkMessage::NewTransaction(_) => &self.transactions,
+            NetworkMessage::Vote(_) => &self.votes,
+        }
+    }
+
+    /// Returns whether a topic is one of the chain's topics.
+    pub fn contains(&self, hash: &gossipsub::TopicHash) -> bool {
+        self.named().iter().any(|(_, topic)| topic.hash() == *hash)
+    }
+}
+
+/// Initializes the P2P network, setting up **GossipSub**, **mDNS** and **Kademlia** for communication.
+///
+/// # Arguments
+///
+/// * `local_key` - The node's identity keypair, also used to sign produced blocks.
+/// * `listen_addrs` - The TCP, QUIC or WebSocket addresses to listen on.
+/// * `swarm_key` - The pre-shared key of the private network, if the node runs in private mode.
+/// * `allowlist` - The only peers allowed to connect, or `None` to accept any peer until
+///   the allowlist is enabled from the command line.
+/// * `chain_id` - The id of the chain, which namespaces the GossipSub topics.
+/// * `light` - Whether to subscribe only to block announcements.
 ///
 /// # Returns
 ///
-/// A tuple containing the **Swarm** (networking entity) and the **GossipSub topic**.
+/// A tuple containing the **Swarm** (networking entity) and the **GossipSub topics**.
 ///
 /// # Errors
 ///
-/// Returns an error if the network fails to initialize.
+/// Returns an error if the network fails to initialize or cannot listen on an address.
 ///
 /// # Example
 ///
 /// ```rust
-/// let (swarm, topic) = init_network().expect("Failed to initialize network");
+/// let local_key = identity::Keypair::generate_ed25519();
+/// let (swarm, topics) = init_network(local_key, &[], None, None, "p2p_network", false).await?;
 /// ```
-pub fn init_network() -> Result<(Swarm<CustomBehaviour>, gossipsub::IdentTopic), Box<dyn Error>> {
+pub async fn init_network(
+    local_key: identity::Keypair,
+    listen_addrs: &[Multiaddr],
+    swarm_key: Option<PreSharedKey>,
+    allowlist: Option<Vec<PeerId>>,
+    chain_id: &str,
+    light: bool,
+) -> Result<(Swarm<CustomBehaviour>, Topics), Box<dyn Error>> {
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .try_init()
         .ok();
 
-    let local_key = identity::Keypair::generate_ed25519();
     let local_peer_id = PeerId::from(local_key.public());
-    println!("New node peer id: {}", local_peer_id.to_string());
-
-    let message_id_fn = |message: &gossipsub::Message| {
-        let mut s = DefaultHasher::new();
-        message.data.hash(&mut s);
-        gossipsub::MessageId::from(s.finish().to_string())
-    };
 
     let gossipsub_config = gossipsub::ConfigBuilder::default()
         .heartbeat_interval(Duration::from_secs(15))
         .validation_mode(gossipsub::ValidationMode::Strict)
-        .message_id_fn(message_id_fn)
+        .validate_messages()
+        .message_id_fn(content_message_id)
+        .duplicate_cache_time(DEDUP_WINDOW)
         .build()?;
 
     let gossipsub = gossipsub::Behaviour::new(
@@ -119,82 +295,374 @@ pub fn init_network() -> Result<(Swarm<CustomBehaviour>, gossipsub::IdentTopic),
         enable_ipv6: false,
         ttl: Duration::from_secs(20),
         query_interval: Duration::from_secs(10),
-        ..Default::default()
     };
 
-    let mdns = mdns::tokio::Behaviour::new(mdns_config, local_peer_id.clone())?;
-    let behaviour = CustomBehaviour { gossipsub, mdns };
+    let mdns = mdns::tokio::Behaviour::new(mdns_config, local_peer_id)?;
+    let kad = discovery::new_kademlia(local_peer_id);
+    let sync = sync::new_sync();
+    let ping = ping::Behaviour::new(ping::Config::new());
+    let identify = identify::Behaviour::new(
+        identify::Config::new(IDENTIFY_PROTOCOL.to_string(), local_key.public())
+            .with_agent_version(sync::USER_AGENT.to_string()),
+    );
+    let blocked = allow_block_list::Behaviour::default();
+    let allowed = Allowlist::new(allowlist);
+    let behaviour = CustomBehaviour { gossipsub, mdns, kad, sync, ping, identify, blocked, allowed };
+
+    let mut swarm = match swarm_key {
+        Some(psk) => libp2p::SwarmBuilder::with_existing_identity(local_key)
+            .with_tokio()
+            .with_other_transport(|key| private_transport(key, psk))?
+            .with_dns()?
+            .with_behaviour(|_| Ok(behaviour))?
+            .build(),
+        None => libp2p::SwarmBuilder::with_existing_identity(local_key)
+            .with_tokio()
+            .with_tcp(tcp::Config::default(), noise::Config::new, yamux::Config::default)?
+            .with_quic()
+            .with_dns()?
+            .with_websocket(noise::Config::new, yamux::Config::default)
+            .await?
+            .with_behaviour(|_| Ok(behaviour))?
+            .build(),
+    };
+
+    for addr in listen_addrs {
+        // The pre-shared key handshake needs a raw stream, which QUIC and WebSocket do not provide.
+        let tcp_only = !addr.iter().any(|protocol| matches!(protocol, Protocol::QuicV1 | Protocol::Ws(_) | Protocol::Wss(_)));
+        if swarm_key.is_some() && !tcp_only {
+            println!("Not listening on {}: private networks only support TCP.", addr);
+            continue;
+        }
+        swarm.listen_on(addr.clone())?;
+    }
 
-    let mut swarm = libp2p::SwarmBuilder::with_existing_identity(local_key)
-        .with_tokio()
-        .with_tcp(tcp::Config::default(), noise::Config::new, || yamux::Config::default())?
-        .with_behaviour(|_| Ok(behaviour))?
-        .build();
+    // Light nodes only follow block announcements, without transaction and vote traffic.
+    // They still fetch and check full blocks through the sync protocol.
+    let topics = Topics::new(chain_id);
+    for (name, topic) in topics.named() {
+        if light && name != "blocks" {
+            continue;
+        }
+        swarm.behaviour_mut().gossipsub.subscribe(topic)?;
+        println!("Subscribed to {}", topic);
+    }
+    let (score_params, score_thresholds) = peer_score_params(&topics);
+    swarm.behaviour_mut().gossipsub.with_peer_score(score_params, score_thresholds)?;
+
+    Ok((swarm, topics))
+}
 
-    swarm.listen_on("/ip4/0.0.0.0/tcp/0".parse()?)?;
-    let topic = gossipsub::IdentTopic::new("p2p_network");
-    swarm.behaviour_mut().gossipsub.subscribe(&topic)?;
+/// Derives the GossipSub id of a message from its content: the hex-encoded SHA-256 of its data.
+///
+/// The id only depends on the bytes of the message, so nodes built with different
+/// toolchains agree on it, and the same message published by two nodes is seen as a
+/// duplicate within the [`DEDUP_WINDOW`].
+fn content_message_id(message: &gossipsub::Message) -> gossipsub::MessageId {
+    gossipsub::MessageId::from(format!("{:x}", Sha256::digest(&message.data)))
+}
+
+/// Builds the transport of a private network: TCP connections that start with a
+/// pre-shared key handshake, then are secured with Noise and multiplexed with Yamux.
+///
+/// A peer without the same key fails the handshake, since neither side can decrypt
+/// the other's Noise messages.
+///
+/// # Arguments
+///
+/// * `local_key` - The node's identity keypair.
+/// * `psk` - The pre-shared key of the private network.
+fn private_transport(local_key: &identity::Keypair, psk: PreSharedKey) -> Result<Boxed<(PeerId, StreamMuxerBox)>, Box<dyn Error + Send + Sync>> {
+    Ok(tcp::tokio::Transport::new(tcp::Config::default())
+        .and_then(move |socket, _| PnetConfig::new(psk).handshake(socket))
+        .upgrade(Version::V1Lazy)
+        .authenticate(noise::Config::new(local_key)?)
+        .multiplex(yamux::Config::default())
+        .timeout(Duration::from_secs(10))
+        .boxed())
+}
+
+/// Builds the **GossipSub peer scoring** parameters.
+///
+/// Scores mostly reflect invalid messages: a peer loses `10 × n²` points for its `n`
+/// recent messages rejected by validation, and is graylisted (all its messages are
+/// dropped) below -80 points, i.e. after 3 invalid messages. The penalty decays by 1%
+/// per second. Delivery-rate penalties are disabled, since blocks are rare on this network.
+///
+/// # Arguments
+///
+/// * `topics` - The GossipSub topics of the chain.
+fn peer_score_params(topics: &Topics) -> (gossipsub::PeerScoreParams, gossipsub::PeerScoreThresholds) {
+    let topic_params = gossipsub::TopicScoreParams {
+        topic_weight: 1.0,
+        time_in_mesh_weight: 0.01,
+        time_in_mesh_quantum: Duration::from_secs(1),
+        time_in_mesh_cap: 100.0,
+        first_message_deliveries_weight: 1.0,
+        first_message_deliveries_decay: 0.5,
+        first_message_deliveries_cap: 5.0,
+        mesh_message_deliveries_weight: 0.0,
+        mesh_failure_penalty_weight: 0.0,
+        invalid_message_deliveries_weight: -10.0,
+        invalid_message_deliveries_decay: 0.99,
+        ..Default::default()
+    };
 
-    Ok((swarm, topic))
+    let mut params = gossipsub::PeerScoreParams::default();
+    for (_, topic) in topics.named() {
+        params.topics.insert(topic.hash(), topic_params.clone());
+    }
+    (params, gossipsub::PeerScoreThresholds::default())
 }
 
-/// Lists all active peers discovered through **mDNS**.
+/// Lists the connected peers, with their GossipSub membership, the tip and version
+/// they reported in their handshake and their diagnostics (agent version, round-trip
+/// time, listen addresses and connections), then the peers discovered through **mDNS** that
+/// are not connected, followed by the peers of the **Kademlia DHT**.
 ///
 /// # Arguments
 ///
 /// * `swarm` - The network swarm instance.
-pub fn list_peers(swarm: &mut Swarm<CustomBehaviour>) {
-    println!("Active peers:");
-    let peers: HashSet<_> = swarm.behaviour().mdns.discovered_nodes().collect();
-    for peer in peers {
-        println!("{:?}", peer);
+/// * `topics` - The GossipSub topics.
+/// * `node` - The state of the local node.
+pub fn list_peers(swarm: &mut Swarm<CustomBehaviour>, topics: &Topics, node: &Node) {
+    if let Some(swarm_key) = &node.swarm_key {
+        println!("Private network (swarm key fingerprint {}): only peers with the same key can connect.", swarm_key.fingerprint());
+    }
+
+    println!("Connected peers:");
+    for (peer, tracked) in node.tracker.peers() {
+        let membership: Vec<String> = topics.named().iter()
+            .map(|(name, topic)| {
+                let hash = topic.hash();
+                let state = if swarm.behaviour().gossipsub.mesh_peers(&hash).any(|mesh_peer| mesh_peer == peer) {
+                    "in the mesh"
+                } else if tracked.topics.contains(&hash) {
+                    "subscribed"
+                } else {
+                    "not subscribed"
+                };
+                format!("{} {}", name, state)
+            })
+            .collect();
+        let handshake = match node.sync.peer_status(peer) {
+            Some(status) => format!(
+                "tip {} ({}), protocol v{}",
+                status.best_height, status.best_hash, status.protocol_version
+            ),
+            None => "no handshake".to_string(),
+        };
+        println!(
+            "{:?} {}, up {}s, reputation {:.0}",
+            peer, handshake, tracked.uptime().as_secs(), node.peers.reputation(peer)
+        );
+
+        let rtt = tracked.rtt.map_or("not measured yet".to_string(), |rtt| format!("{:.1}ms", rtt.as_secs_f64() * 1000.0));
+        let agent = tracked.agent_version.as_deref()
+            .or(node.sync.peer_status(peer).map(|status| status.user_agent.as_str()))
+            .unwrap_or("not identified yet");
+        println!("    agent: {}, RTT: {}", agent, rtt);
+        let listen_addrs: Vec<String> = tracked.listen_addrs.iter().map(|a| a.to_string()).collect();
+        println!("    listen addresses: [{}]", listen_addrs.join(", "));
+        println!("    topics: {}", membership.join(", "));
+        for connection in tracked.connections.values() {
+            println!("    connection: {}", connection);
+        }
+    }
+
+    println!("Discovered peers, not connected:");
+    let discovered: HashSet<PeerId> = swarm.behaviour().mdns.discovered_nodes()
+        .filter(|peer| swarm.behaviour().is_allowed(peer) && !node.tracker.is_connected(peer))
+        .copied()
+        .collect();
+    for peer in discovered {
+        match node.connections.last_failure(&peer) {
+            Some(failure) if node.swarm_key.is_some() => println!("{:?} (unreachable, it may not have the swarm key: {})", peer, failure),
+            Some(failure) => println!("{:?} (unreachable: {})", peer, failure),
+            None => println!("{:?}", peer),
+        }
+    }
+
+    println!("DHT peers:");
+    for (peer, addresses) in discovery::dht_peers(swarm) {
+        let addresses: Vec<String> = addresses.iter().map(|a| a.to_string()).collect();
+        println!("{:?} at [{}]", peer, addresses.join(", "));
     }
 }
 
-/// Broadcasts a message to all connected peers.
+/// Broadcasts a message to the peers in the GossipSub mesh of its topic.
+///
+/// Peers that just subscribed to the topic may not be in the mesh yet, in which case
+/// publishing would fail, so the mesh decides whether the message can be sent.
 ///
 /// # Arguments
 ///
 /// * `swarm` - The network swarm instance.
-/// * `topic` - The GossipSub topic to broadcast on.
+/// * `topics` - The GossipSub topics; the message is published on the topic of its kind.
 /// * `msg` - The message to be sent.
 ///
-/// If no peers are connected, the function logs a warning.
+/// # Returns
+///
+/// - `true` if the message was published, or already had been.
+/// - `false` if the node has no peer in the mesh of the topic or publishing failed.
 pub fn broadcast_message(
     swarm: &mut Swarm<CustomBehaviour>,
-    topic: &gossipsub::IdentTopic,
+    topics: &Topics,
+    msg: NetworkMessage,
+) -> bool {
+    let topic = topics.for_message(&msg);
+    let topic_hash = topic.hash();
+    let gossipsub = &swarm.behaviour().gossipsub;
+    let subscribers = gossipsub.all_peers()
+        .filter(|(_, topics)| topics.contains(&&topic_hash))
+        .count();
+    let mesh_peers = gossipsub.mesh_peers(&topic_hash).count();
+
+    if mesh_peers == 0 {
+        println!("No peer in the mesh of {} ({} subscribed). Message was not broadcasted.", topic, subscribers);
+        return false;
+    }
+
+    let data = wire::encode(&msg);
+    match swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
+        Ok(_) => {
+            println!("Message broadcasted to {} subscribed peers ({} in the mesh).", subscribers, mesh_peers);
+            true
+        }
+        Err(gossipsub::PublishError::Duplicate) => {
+            println!("Message was already broadcasted.");
+            true
+        }
+        Err(e) => {
+            println!("Failed to broadcast: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Broadcasts a message, or queues it in the outbox until the node has a peer in the mesh of its topic.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `topics` - The GossipSub topics.
+/// * `outbox` - The queue of unsent messages.
+/// * `msg` - The message to be sent.
+pub fn broadcast_or_queue(
+    swarm: &mut Swarm<CustomBehaviour>,
+    topics: &Topics,
+    outbox: &mut Outbox,
     msg: NetworkMessage,
 ) {
-    let connected_peers: Vec<_> = swarm.behaviour().mdns.discovered_nodes().collect();
+    let queued = msg.clone();
+    if !broadcast_message(swarm, topics, msg) {
+        outbox.push(queued);
+        println!("Message queued until a peer joins the mesh ({} queued).", outbox.len());
+    }
+}
 
-    if connected_peers.is_empty() {
-        println!("No active peers in the network. Message was not broadcasted.");
+/// Broadcasts the messages queued in the outbox, dropping the stale ones, once the
+/// node has a peer in the mesh of the topic of a queued message.
+///
+/// Messages that still cannot be sent go back to the outbox.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `topics` - The GossipSub topics.
+/// * `node` - The state of the local node.
+pub fn flush_outbox(swarm: &mut Swarm<CustomBehaviour>, topics: &Topics, node: &mut Node) {
+    if node.outbox.is_empty() {
+        return;
+    }
+    let gossipsub = &swarm.behaviour().gossipsub;
+    let ready = node.outbox.messages()
+        .any(|msg| gossipsub.mesh_peers(&topics.for_message(msg).hash()).next().is_some());
+    if !ready {
         return;
     }
+    let messages = node.outbox.take_fresh(&node.blockchain, &node.mempool);
+    println!("Sending {} queued messages.", messages.len());
+    for msg in messages {
+        broadcast_or_queue(swarm, topics, &mut node.outbox, msg);
+    }
+}
 
-    let data = serde_json::to_vec(&msg).unwrap();
-    match swarm.behaviour_mut().gossipsub.publish(topic.clone(), data) {
-        Ok(_) => println!("Message broadcasted to network."),
-        Err(e) => println!("Failed to broadcast: {:?}", e),
+/// Broadcasts the local node's finality votes, queueing them in the outbox if no peer
+/// is in the mesh of the votes topic.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `topics` - The GossipSub topics.
+/// * `outbox` - The outbox of messages waiting for a mesh.
+/// * `votes` - The votes to be sent.
+pub fn broadcast_votes(
+    swarm: &mut Swarm<CustomBehaviour>,
+    topics: &Topics,
+    outbox: &mut Outbox,
+    votes: Vec<Vote>,
+) {
+    for vote in votes {
+        let serialized_vote = serde_json::to_string(&vote).unwrap();
+        broadcast_or_queue(swarm, topics, outbox, NetworkMessage::Vote(serialized_vote));
     }
 }
 
-/// Handles events related to **mDNS peer discovery** asynchronously.
+/// Handles network events until the future is dropped, e.g. by a timeout while the node
+/// initializes.
 ///
 /// # Arguments
 ///
 /// * `swarm` - The network swarm instance.
-pub async fn handle_mdns(swarm: &mut Swarm<CustomBehaviour>) {
+/// * `topics` - The GossipSub topics.
+/// * `node` - The state of the local node.
+pub async fn handle_events(swarm: &mut Swarm<CustomBehaviour>, topics: &Topics, node: &mut Node) {
     loop {
-        if let Some(event) = swarm.next().await {
-            match event {
-                SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
-                    for (peer_id, addr) in &peers {
-                        println!("Discovered peer: {} at {}", peer_id, addr);
-                    }
-                }
-                _ => {}
-            }
+        let event = swarm.select_next_some().await;
+        handle_event(event, swarm, topics, node);
+    }
+}
+
+/// Prints a new listen address as a full multiaddr ending in `/p2p/<peer id>`,
+/// ready to be given to other nodes with `--bootstrap` or `Connect`.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `address` - The address the node now listens on.
+fn print_listen_addr(swarm: &Swarm<CustomBehaviour>, address: Multiaddr) {
+    println!("Listening on {}", address.with(Protocol::P2p(*swarm.local_peer_id())));
+}
+
+/// Adds peers discovered through **mDNS** to the DHT and connects to them.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `peers` - The discovered peers and their addresses.
+fn handle_discovered(swarm: &mut Swarm<CustomBehaviour>, peers: Vec<(PeerId, Multiaddr)>) {
+    for (peer_id, addr) in peers {
+        println!("Discovered peer: {} at {}", peer_id, addr);
+        discovery::add_peer(swarm, peer_id, addr);
+    }
+}
+
+/// Forgets the addresses of peers whose **mDNS** records expired.
+///
+/// The addresses are removed from the DHT unless a connection to the peer is still
+/// open, so the node stops redialing peers that left the local network.
+///
+/// # Arguments
+///
+/// * `swarm` - The network swarm instance.
+/// * `node` - The state of the local node.
+/// * `peers` - The expired peers and their addresses.
+fn handle_expired(swarm: &mut Swarm<CustomBehaviour>, node: &Node, peers: Vec<(PeerId, Multiaddr)>) {
+    for (peer_id, addr) in peers {
+        println!("Expired peer: {} at {}", peer_id, addr);
+        if !node.tracker.is_connected(&peer_id) {
+            swarm.behaviour_mut().kad.remove_address(&peer_id, &addr);
         }
     }
 }
@@ -205,50 +673,237 @@ pub async fn handle_mdns(swarm: &mut Swarm<CustomBehaviour>) {
 ///
 /// * `event` - The event to be processed.
 /// * `swarm` - The network swarm instance.
-/// * `topic` - The GossipSub topic.
-/// * `local_blockchain` - The local blockchain instance.
+/// * `topics` - The GossipSub topics.
+/// * `node` - The state of the local node.
 pub fn handle_event(
     event: SwarmEvent<CustomBehaviourEvent>,
     swarm: &mut Swarm<CustomBehaviour>,
-    topic: &gossipsub::IdentTopic,
-    local_blockchain: &mut Blockchain,
+    topics: &Topics,
+    node: &mut Node,
 ) {
     match event {
-        SwarmEvent::Behaviour(CustomBehaviourEvent::GossipSub(gossipsub::Event::Message { message, .. })) => {
-            if let Ok(decoded) = serde_json::from_slice::<NetworkMessage>(&message.data) {
-                match decoded {
-                    NetworkMessage::NewBlock(block_data) => {
-                        println!("New Block Received: {:?}", block_data);
-                        let block: Block = match serde_json::from_str(&block_data) {
-                            Ok(b) => b,
-                            Err(e) => {
-                                println!("Failed to deserialize Block: {:?}", e);
-                                return;
-                            }
-                        };
-                        
-                        if !local_blockchain.add_block(block) {
-                            println!("NewBlock Error!");
-                            return;
-                        }
-                        println!("Successfully added the block to local blockchain!");
-                    }
-
-                    NetworkMessage::ChainRequest => {
-                        let serialized_blocks: Vec<String> = local_blockchain.get_blocks()
-                            .iter()
-                            .map(|block| serde_json::to_string(block).unwrap())
-                            .collect();
-                        
-                        let response = NetworkMessage::ChainResponse(serialized_blocks);
-                        let data = serde_json::to_vec(&response).unwrap();
-                        swarm.behaviour_mut().gossipsub.publish(topic.clone(), data).unwrap();
-                    }
-
-                    _ => println!("⚠️ Received invalid message from {:?}", message.source),
+        SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, num_established, .. } => {
+            node.tracker.on_connection_established(peer_id, connection_id, &endpoint);
+            node.connections.on_connected(&peer_id);
+            if num_established.get() == 1 {
+                node.sync.request_status(swarm, peer_id);
+            }
+        }
+        SwarmEvent::ConnectionClosed { peer_id, connection_id, num_established, .. } => {
+            node.tracker.on_connection_closed(&peer_id, connection_id);
+            if num_established == 0 {
+                node.connections.on_disconnected(&peer_id);
+                node.sync.on_disconnected(&peer_id, &mut node.blockchain);
+            }
+        }
+        SwarmEvent::OutgoingConnectionError { peer_id: Some(peer_id), error, .. } => {
+            node.connections.on_dial_failed(&peer_id, &error);
+        }
+        SwarmEvent::NewListenAddr { address, .. } => print_listen_addr(swarm, address),
+        SwarmEvent::Behaviour(CustomBehaviourEvent::GossipSub(gossipsub::Event::Message { propagation_source, message_id, message })) => {
+            // Messages are only forwarded to other peers once they have been validated.
+            if let Some(acceptance) = handle_message(message, &message_id, propagation_source, swarm, topics, node) {
+                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &propagation_source, acceptance);
+            }
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::GossipSub(gossipsub::Event::Subscribed { peer_id, topic: subscribed })) => {
+            // A peer joining a topic is usually grafted into the mesh right away, and can
+            // then receive the messages queued while no one could.
+            let joined = topics.contains(&subscribed);
+            node.tracker.on_subscribed(&peer_id, subscribed);
+            if joined {
+                flush_outbox(swarm, topics, node);
+            }
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::GossipSub(gossipsub::Event::Unsubscribed { peer_id, topic: unsubscribed })) => {
+            node.tracker.on_unsubscribed(&peer_id, &unsubscribed);
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Ping(ping::Event { peer, result, .. })) => match result {
+            Ok(rtt) => node.tracker.on_ping(&peer, rtt),
+            Err(e) => println!("Ping to {} failed: {}", peer, e),
+        },
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Identify(identify::Event::Received { peer_id, info, .. })) => {
+            node.tracker.on_identified(&peer_id, info.agent_version, info.listen_addrs);
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Discovered(peers))) => {
+            handle_discovered(swarm, peers);
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Mdns(mdns::Event::Expired(peers))) => {
+            handle_expired(swarm, node, peers);
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Kademlia(event)) => {
+            discovery::handle_kad_event(swarm, event);
+        }
+        SwarmEvent::Behaviour(CustomBehaviourEvent::Sync(event)) => {
+            // Drop requests from peers over their quota; the dropped channel fails the request.
+            if let request_response::Event::Message { peer, message: request_response::Message::Request { request, .. }, .. } = &event {
+                if !node.rate_limits.allow(*peer, request.kind()) {
+                    println!("Dropped {:?} request from {}: rate limit exceeded.", request.kind(), peer);
+                    node.peers.report(swarm, *peer, Misbehaviour::RateLimitExceeded);
+                    return;
                 }
             }
+
+            node.sync.handle_event(event, swarm, &mut node.blockchain, &mut node.mempool, &mut node.finality);
+            for (peer, misbehaviour) in node.sync.take_misbehaving() {
+                node.peers.report(swarm, peer, misbehaviour);
+            }
+            broadcast_votes(swarm, topics, &mut node.outbox, node.sync.take_votes());
         }
         _ => {}
     }
 }
+
+/// Processes a **blockchain message** received over GossipSub.
+///
+/// # Arguments
+///
+/// * `message` - The received GossipSub message.
+/// * `message_id` - The GossipSub id of the message.
+/// * `propagation_source` - The peer that relayed the message.
+/// * `swarm` - The network swarm instance.
+/// * `topics` - The GossipSub topics.
+/// * `node` - The state of the local node.
+///
+/// # Returns
+///
+/// The validation result reported to GossipSub:
+/// - `Accept` if the message is valid and should be forwarded to other peers.
+/// - `Reject` if the message is invalid, which penalizes the peers that sent it.
+/// - `Ignore` if the message is over the peer's rate limit, or valid but not useful
+///   (already known, stale, or ahead of the local chain), so it is not forwarded.
+/// - `None` if the message announces a block being fetched: the syncer reports the
+///   result once the block has arrived and been checked.
+fn handle_message(
+    message: gossipsub::Message,
+    message_id: &gossipsub::MessageId,
+    propagation_source: PeerId,
+    swarm: &mut Swarm<CustomBehaviour>,
+    topics: &Topics,
+    node: &mut Node,
+) -> Option<gossipsub::MessageAcceptance> {
+    let decoded = match wire::decode(&message.data) {
+        Ok(Decoded::Known(decoded)) => decoded,
+
+        // Messages of unknown types most likely come from newer nodes: skip them without
+        // penalty, but do not forward what cannot be validated.
+        Ok(Decoded::Unknown { version, kind }) => {
+            if !node.rate_limits.allow(propagation_source, MessageKind::UnknownMessage) {
+                println!("Dropped unknown message from {}: rate limit exceeded.", propagation_source);
+            } else {
+                println!("Ignored message of unknown type {} (wire version {}) from {}.", kind, version, propagation_source);
+            }
+            return Some(gossipsub::MessageAcceptance::Ignore);
+        }
+        Err(e) => {
+            println!("Undecodable message from {}: {}", propagation_source, e);
+            node.peers.report(swarm, propagation_source, Misbehaviour::UndecodableMessage);
+            return Some(gossipsub::MessageAcceptance::Reject);
+        }
+    };
+
+    // Each kind of message has its own topic: one published elsewhere is malformed.
+    if message.topic != topics.for_message(&decoded).hash() {
+        println!("Rejected {:?} message from {}: wrong topic {}.", decoded.kind(), propagation_source, message.topic);
+        node.peers.report(swarm, propagation_source, Misbehaviour::WrongTopic);
+        return Some(gossipsub::MessageAcceptance::Reject);
+    }
+
+    // Drop messages over the relaying peer's quota, without forwarding them. The peer
+    // is not penalized, as it may only be relaying a flood from another peer.
+    if !node.rate_limits.allow(propagation_source, decoded.kind()) {
+        println!("Dropped {:?} message from {}: rate limit exceeded.", decoded.kind(), propagation_source);
+        return Some(gossipsub::MessageAcceptance::Ignore);
+    }
+
+    match decoded {
+        NetworkMessage::NewBlock { hash, height } => {
+            println!("Block {} announced: {}", height, hash);
+            let local_height = node.blockchain.get_blocks().len() - 1;
+
+            // A block beyond the next height means we are behind: sync with the relaying peer.
+            if height > local_height + 1 {
+                println!("Block {} is ahead of the local chain, synchronizing with {}.", height, propagation_source);
+                node.sync.request_status(swarm, propagation_source);
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+
+            // A block at or below the local tip is already known or on a competing fork.
+            if height <= local_height || !node.sync.fetch_announced(swarm, propagation_source, message_id.clone(), hash) {
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+            None
+        }
+
+        NetworkMessage::NewTransaction(tx_data) => {
+            let tx: Transaction = match serde_json::from_str(&tx_data) {
+                Ok(tx) => tx,
+                Err(e) => {
+                    println!("Failed to deserialize Transaction: {:?}", e);
+                    node.peers.report(swarm, propagation_source, Misbehaviour::InvalidTransaction);
+                    return Some(gossipsub::MessageAcceptance::Reject);
+                }
+            };
+
+            if !tx.verify_signature() {
+                println!("Transaction rejected: invalid signature from {}.", tx.sender);
+                node.peers.report(swarm, propagation_source, Misbehaviour::InvalidTransaction);
+                return Some(gossipsub::MessageAcceptance::Reject);
+            }
+            if node.mempool.contains(&tx.id()) {
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+
+            // A transaction that cannot apply to the chain state (stale nonce, missing
+            // funds) may still be valid later or on another fork: drop it without penalty.
+            if let Err(reason) = node.mempool.check(&tx, node.blockchain.get_state()) {
+                println!("Transaction ignored: {}", reason);
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+            if !node.mempool.add(tx) {
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+            println!("New transaction added to the mempool.");
+            Some(gossipsub::MessageAcceptance::Accept)
+        }
+
+        NetworkMessage::Vote(vote_data) => {
+            let vote: Vote = match serde_json::from_str(&vote_data) {
+                Ok(vote) => vote,
+                Err(e) => {
+                    println!("Failed to deserialize Vote: {:?}", e);
+                    node.peers.report(swarm, propagation_source, Misbehaviour::InvalidVote);
+                    return Some(gossipsub::MessageAcceptance::Reject);
+                }
+            };
+
+            if !vote.verify_signature() {
+                println!("Vote rejected: invalid signature from {}.", vote.voter);
+                node.peers.report(swarm, propagation_source, Misbehaviour::InvalidVote);
+                return Some(gossipsub::MessageAcceptance::Reject);
+            }
+
+            // Votes for final blocks are useless, and votes far ahead of the local chain cannot be checked yet.
+            let local_height = node.blockchain.get_blocks().len() - 1;
+            if vote.height <= node.blockchain.finalized_height() || vote.height > local_height + finality::VOTE_WINDOW {
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+            // The voter may have become a validator in a block the node has not received yet.
+            if !consensus::voting_weights(&node.blockchain).contains_key(&vote.voter) {
+                println!("Vote ignored: {} is not a validator at the local tip.", vote.voter);
+                return Some(gossipsub::MessageAcceptance::Ignore);
+            }
+            match node.finality.on_vote(&mut node.blockchain, vote) {
+                Ok(votes) => {
+                    broadcast_votes(swarm, topics, &mut node.outbox, votes);
+                    Some(gossipsub::MessageAcceptance::Accept)
+                }
+                Err(reason) => {
+                    println!("Vote rejected: {}", reason);
+                    node.peers.report(swarm, propagation_source, Misbehaviour::InvalidVote);
+                    Some(gossipsub::MessageAcceptance::Reject)
+                }
+            }
+        }
+    }
+}
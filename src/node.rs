@@ -0,0 +1,46 @@
+//! This module defines the **node state**: everything the node keeps besides its network swarm.
+
+use libp2p::pnet::PreSharedKey;
+
+use crate::blockchain::Blockchain;
+use crate::connections::ConnectionManager;
+use crate::finality::FinalityGadget;
+use crate::mempool::Mempool;
+use crate::outbox::Outbox;
+use crate::peer_manager::PeerManager;
+use crate::peer_tracker::PeerTracker;
+use crate::rate_limit::RateLimiter;
+use crate::sync::Syncer;
+
+/// The state of the local node, shared by the command loop and the network event handlers.
+pub struct Node {
+    /// The local blockchain instance.
+    pub blockchain: Blockchain,
+
+    /// Transactions waiting to be included in a block.
+    pub mempool: Mempool,
+
+    /// Messages waiting for a peer to be broadcast to.
+    pub outbox: Outbox,
+
+    /// Validator votes finalizing blocks of the local blockchain.
+    pub finality: FinalityGadget,
+
+    /// Connections to bootstrap peers.
+    pub connections: ConnectionManager,
+
+    /// Peers the node is connected to and their GossipSub subscriptions.
+    pub tracker: PeerTracker,
+
+    /// Reputation of peers and bans.
+    pub peers: PeerManager,
+
+    /// Per-peer rate limits of incoming messages.
+    pub rate_limits: RateLimiter,
+
+    /// Progress of the chain synchronization with peers.
+    pub sync: Syncer,
+
+    /// The pre-shared key of the private network, if the node runs in private mode.
+    pub swarm_key: Option<PreSharedKey>,
+}
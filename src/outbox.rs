@@ -0,0 +1,92 @@
+//! This module implements the **outbox**, which keeps the messages the node could not
+//! broadcast because it had no peer in the GossipSub mesh of their topic.
+//!
+//! The queued messages are sent again once a mesh forms, so a block produced
+//! while offline still reaches the network. Messages that became stale meanwhile are
+//! dropped instead: an announcement of a block that is no longer the local tip, a
+//! transaction no longer in the mempool, or a vote for a block no longer in the chain.
+
+use std::collections::VecDeque;
+
+use crate::blockchain::Blockchain;
+use crate::finality::Vote;
+use crate::mempool::Mempool;
+use crate::network::NetworkMessage;
+use crate::transaction::Transaction;
+
+/// Number of messages the outbox keeps before dropping the oldest ones.
+const OUTBOX_CAPACITY: usize = 256;
+
+/// Messages waiting for a peer to broadcast them to, oldest first.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    /// The queued messages.
+    messages: VecDeque<NetworkMessage>,
+}
+
+impl Outbox {
+    /// Creates an empty outbox.
+    pub fn new() -> Self {
+        Outbox::default()
+    }
+
+    /// Queues a message. A block announcement replaces the announcements already queued,
+    /// as peers fetch the blocks below the newest tip while synchronizing.
+    pub fn push(&mut self, message: NetworkMessage) {
+        if matches!(message, NetworkMessage::NewBlock { .. }) {
+            self.messages.retain(|queued| !matches!(queued, NetworkMessage::NewBlock { .. }));
+        }
+        if self.messages.len() == OUTBOX_CAPACITY {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    /// Returns the number of queued messages.
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    /// Returns the queued messages, oldest first.
+    pub fn messages(&self) -> impl Iterator<Item = &NetworkMessage> {
+        self.messages.iter()
+    }
+
+    /// Returns whether no message is queued.
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// Empties the outbox.
+    ///
+    /// # Arguments
+    ///
+    /// * `chain` - The local blockchain.
+    /// * `mempool` - The local mempool.
+    ///
+    /// # Returns
+    ///
+    /// The queued messages that are still relevant, oldest first.
+    pub fn take_fresh(&mut self, chain: &Blockchain, mempool: &Mempool) -> Vec<NetworkMessage> {
+        let messages: Vec<NetworkMessage> = self.messages.drain(..).collect();
+        let total = messages.len();
+        let fresh: Vec<NetworkMessage> = messages.into_iter()
+            .filter(|message| is_fresh(message, chain, mempool))
+            .collect();
+        if fresh.len() < total {
+            println!("Dropped {} stale queued messages.", total - fresh.len());
+        }
+        fresh
+    }
+}
+
+/// Returns whether a queued message is still worth broadcasting.
+fn is_fresh(message: &NetworkMessage, chain: &Blockchain, mempool: &Mempool) -> bool {
+    match message {
+        NetworkMessage::NewBlock { hash, .. } => chain.get_last_block().is_some_and(|tip| tip.hash == *hash),
+        NetworkMessage::NewTransaction(tx_data) => serde_json::from_str::<Transaction>(tx_data)
+            .is_ok_and(|tx| mempool.contains(&tx.id())),
+        NetworkMessage::Vote(vote_data) => serde_json::from_str::<Vote>(vote_data)
+            .is_ok_and(|vote| chain.get_blocks().get(vote.height).is_some_and(|block| block.hash == vote.block_hash)),
+    }
+}
@@ -0,0 +1,276 @@
+//! This module implements the **peer manager**, which keeps track of how peers behave.
+//!
+//! Every misbehaviour (an undecodable message, an invalid block, a message over the
+//! peer's rate limit, ...) lowers the peer's reputation, which recovers over time. A
+//! peer whose reputation falls below [`BAN_THRESHOLD`] is disconnected and banned.
+//! Bans, whether automatic or from the `Ban` command, are persisted to a JSON file so
+//! they survive restarts.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use libp2p::{swarm::Swarm, PeerId};
+use serde::{Serialize, Deserialize};
+
+use crate::network::CustomBehaviour;
+
+/// Reputation below which a peer is banned.
+const BAN_THRESHOLD: f64 = -100.0;
+
+/// How long a peer is banned when its reputation falls below [`BAN_THRESHOLD`].
+const AUTO_BAN_DURATION: Duration = Duration::from_secs(3600);
+
+/// Factor a reputation is multiplied by every second, so penalties wear off
+/// (half-life of about 70 seconds).
+const REPUTATION_DECAY: f64 = 0.99;
+
+/// A misbehaviour that lowers a peer's reputation.
+#[derive(Debug, Clone, Copy)]
+pub enum Misbehaviour {
+    /// A GossipSub message that is not a valid `NetworkMessage`.
+    UndecodableMessage,
+
+    /// A GossipSub message published on the topic of another kind of message.
+    WrongTopic,
+
+    /// A block breaking the chain rules or badly signed.
+    InvalidBlock,
+
+    /// A badly signed or malformed transaction.
+    InvalidTransaction,
+
+    /// A badly signed, malformed or equivocating finality vote.
+    InvalidVote,
+
+    /// Sync headers or blocks that do not match the peer's own chain.
+    InvalidSyncResponse,
+
+    /// A sync request over the peer's rate limit (see [`crate::rate_limit`]).
+    RateLimitExceeded,
+}
+
+impl Misbehaviour {
+    /// Returns the reputation lost for the misbehaviour.
+    fn penalty(self) -> f64 {
+        match self {
+            Misbehaviour::UndecodableMessage => 25.0,
+            Misbehaviour::WrongTopic => 25.0,
+            Misbehaviour::InvalidBlock => 50.0,
+            Misbehaviour::InvalidTransaction => 20.0,
+            Misbehaviour::InvalidVote => 20.0,
+            Misbehaviour::InvalidSyncResponse => 50.0,
+            Misbehaviour::RateLimitExceeded => 10.0,
+        }
+    }
+}
+
+impl fmt::Display for Misbehaviour {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = match self {
+            Misbehaviour::UndecodableMessage => "undecodable message",
+            Misbehaviour::WrongTopic => "message on the wrong topic",
+            Misbehaviour::InvalidBlock => "invalid block",
+            Misbehaviour::InvalidTransaction => "invalid transaction",
+            Misbehaviour::InvalidVote => "invalid vote",
+            Misbehaviour::InvalidSyncResponse => "invalid sync response",
+            Misbehaviour::RateLimitExceeded => "rate limit exceeded",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+/// A ban as stored on disk.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Ban {
+    /// The banned peer.
+    peer_id: String,
+
+    /// When the ban expires, in seconds since the UNIX epoch.
+    until: u64,
+
+    /// Why the peer was banned.
+    reason: String,
+}
+
+/// Tracks the reputation of peers and bans misbehaving ones.
+#[derive(Debug)]
+pub struct PeerManager {
+    /// Reputation of peers that misbehaved recently. Peers in good standing are absent.
+    reputations: HashMap<PeerId, f64>,
+
+    /// Banned peers.
+    bans: HashMap<PeerId, Ban>,
+
+    /// When reputations last decayed.
+    last_decay: Instant,
+
+    /// The file bans are persisted to.
+    path: PathBuf,
+}
+
+impl PeerManager {
+    /// Loads the bans persisted in `path` and blocks the peers that are still banned.
+    ///
+    /// A missing file is not an error: the node simply starts without bans.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `path` - Path to the ban list file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be read or parsed. Entries with an
+    /// invalid peer id are skipped.
+    pub fn load(swarm: &mut Swarm<CustomBehaviour>, path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let mut manager = PeerManager {
+            reputations: HashMap::new(),
+            bans: HashMap::new(),
+            last_decay: Instant::now(),
+            path: path.to_path_buf(),
+        };
+        if !path.exists() {
+            return Ok(manager);
+        }
+
+        let bans: Vec<Ban> = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let now = unix_now();
+        for ban in bans {
+            if ban.until <= now {
+                continue;
+            }
+            // A corrupt entry only loses its own ban.
+            let Ok(peer_id) = ban.peer_id.parse::<PeerId>() else {
+                println!("Skipped ban of invalid peer id {} in {}.", ban.peer_id, path.display());
+                continue;
+            };
+            swarm.behaviour_mut().blocked.block_peer(peer_id);
+            manager.bans.insert(peer_id, ban);
+        }
+        Ok(manager)
+    }
+
+    /// Lowers a peer's reputation for a misbehaviour, banning it below [`BAN_THRESHOLD`].
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The misbehaving peer.
+    /// * `misbehaviour` - What the peer did.
+    pub fn report(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId, misbehaviour: Misbehaviour) {
+        let reputation = self.reputations.entry(peer_id).or_insert(0.0);
+        *reputation -= misbehaviour.penalty();
+        println!("Peer {} misbehaved ({}), reputation {:.0}.", peer_id, misbehaviour, reputation);
+
+        if *reputation < BAN_THRESHOLD {
+            self.reputations.remove(&peer_id);
+            self.ban(swarm, peer_id, AUTO_BAN_DURATION, &format!("reputation too low after {}", misbehaviour));
+        }
+    }
+
+    /// Returns the reputation of a peer: `0` in good standing, negative after misbehaviour.
+    pub fn reputation(&self, peer_id: &PeerId) -> f64 {
+        self.reputations.get(peer_id).copied().unwrap_or(0.0)
+    }
+
+    /// Bans a peer for `duration`: its connections are closed and new ones refused.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The peer to ban.
+    /// * `duration` - How long the ban lasts.
+    /// * `reason` - Why the peer is banned, shown by `List Bans`.
+    pub fn ban(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId, duration: Duration, reason: &str) {
+        swarm.behaviour_mut().blocked.block_peer(peer_id);
+        self.bans.insert(peer_id, Ban {
+            peer_id: peer_id.to_string(),
+            until: unix_now().saturating_add(duration.as_secs()),
+            reason: reason.to_string(),
+        });
+        println!("Banned {} for {:?}: {}.", peer_id, duration, reason);
+        self.save();
+    }
+
+    /// Lifts the ban of a peer.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the peer was banned.
+    pub fn unban(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId) -> bool {
+        if self.bans.remove(&peer_id).is_none() {
+            return false;
+        }
+        swarm.behaviour_mut().blocked.unblock_peer(peer_id);
+        self.save();
+        true
+    }
+
+    /// Prints the banned peers, with the remaining time and the reason of each ban.
+    pub fn print_bans(&self) {
+        if self.bans.is_empty() {
+            println!("No banned peers.");
+            return;
+        }
+
+        let now = unix_now();
+        println!("Banned peers:");
+        for (peer_id, ban) in &self.bans {
+            println!("{} for {}s more: {}", peer_id, ban.until.saturating_sub(now), ban.reason);
+        }
+    }
+
+    /// Decays reputations and lifts expired bans.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    pub fn tick(&mut self, swarm: &mut Swarm<CustomBehaviour>) {
+        let factor = REPUTATION_DECAY.powf(self.last_decay.elapsed().as_secs_f64());
+        self.last_decay = Instant::now();
+        for reputation in self.reputations.values_mut() {
+            *reputation *= factor;
+        }
+        self.reputations.retain(|_, reputation| *reputation < -1.0);
+
+        let now = unix_now();
+        let expired: Vec<PeerId> = self.bans.iter()
+            .filter(|(_, ban)| ban.until <= now)
+            .map(|(peer_id, _)| *peer_id)
+            .collect();
+        if expired.is_empty() {
+            return;
+        }
+        for peer_id in expired {
+            self.bans.remove(&peer_id);
+            swarm.behaviour_mut().blocked.unblock_peer(peer_id);
+            println!("Ban of {} expired.", peer_id);
+        }
+        self.save();
+    }
+
+    /// Writes the ban list to its file.
+    fn save(&self) {
+        let bans: Vec<&Ban> = self.bans.values().collect();
+        let result = serde_json::to_string_pretty(&bans)
+            .map_err(|e| e.to_string())
+            .and_then(|data| fs::write(&self.path, data).map_err(|e| e.to_string()));
+        if let Err(e) = result {
+            println!("Failed to save the ban list: {}", e);
+        }
+    }
+}
+
+/// Returns the current time in seconds since the UNIX epoch.
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
@@ -0,0 +1,172 @@
+//! This module implements the **peer tracker**, the node's view of the peers it is
+//! actually connected to.
+//!
+//! mDNS reports peers that have been seen on the local network, which does not mean
+//! a connection to them is open or that they take part in GossipSub. The tracker is
+//! instead fed by the swarm's connection events and by GossipSub subscriptions, so
+//! `List Peers` shows real connections and the topics each peer subscribed to.
+//!
+//! It also records the diagnostics of each peer: the round-trip time measured by the
+//! **ping** protocol, the agent version and listen addresses the peer reported through
+//! the **identify** protocol, and the direction, transport and age of each connection.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    core::ConnectedPoint, gossipsub::TopicHash, multiaddr::Protocol, swarm::ConnectionId, Multiaddr, PeerId,
+};
+
+/// An open connection to a peer.
+#[derive(Debug)]
+pub struct TrackedConnection {
+    /// Whether the local node dialed the peer, rather than the peer dialing it.
+    pub outbound: bool,
+
+    /// The address of the peer on this connection.
+    pub remote_addr: Multiaddr,
+
+    /// When the connection was established.
+    pub established: Instant,
+}
+
+impl TrackedConnection {
+    /// Returns how long the connection has been open.
+    pub fn uptime(&self) -> Duration {
+        self.established.elapsed()
+    }
+
+    /// Returns the transport the connection runs over, read from the remote address.
+    pub fn transport(&self) -> &'static str {
+        let protocols: Vec<Protocol> = self.remote_addr.iter().collect();
+        if protocols.iter().any(|protocol| matches!(protocol, Protocol::QuicV1)) {
+            "QUIC"
+        } else if protocols.iter().any(|protocol| matches!(protocol, Protocol::Ws(_) | Protocol::Wss(_))) {
+            "WebSocket"
+        } else if protocols.iter().any(|protocol| matches!(protocol, Protocol::Tcp(_))) {
+            "TCP"
+        } else {
+            "unknown"
+        }
+    }
+}
+
+impl fmt::Display for TrackedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.outbound { "outbound" } else { "inbound" };
+        write!(f, "{} {} via {}, up {}s", direction, self.transport(), self.remote_addr, self.uptime().as_secs())
+    }
+}
+
+/// A connected peer.
+#[derive(Debug, Default)]
+pub struct TrackedPeer {
+    /// The open connections to the peer.
+    pub connections: HashMap<ConnectionId, TrackedConnection>,
+
+    /// The GossipSub topics the peer subscribed to.
+    pub topics: HashSet<TopicHash>,
+
+    /// The last round-trip time measured by ping, if any.
+    pub rtt: Option<Duration>,
+
+    /// The agent version the peer reported through identify, if it did.
+    pub agent_version: Option<String>,
+
+    /// The addresses the peer reported listening on through identify.
+    pub listen_addrs: Vec<Multiaddr>,
+}
+
+impl TrackedPeer {
+    /// Returns how long the peer has been connected, i.e. the age of its oldest connection.
+    pub fn uptime(&self) -> Duration {
+        self.connections.values().map(TrackedConnection::uptime).max().unwrap_or_default()
+    }
+}
+
+/// Tracks the connected peers, their GossipSub subscriptions and their diagnostics.
+#[derive(Debug, Default)]
+pub struct PeerTracker {
+    /// Peers with at least one open connection.
+    peers: HashMap<PeerId, TrackedPeer>,
+}
+
+impl PeerTracker {
+    /// Creates a tracker with no connected peers.
+    pub fn new() -> Self {
+        PeerTracker::default()
+    }
+
+    /// Records a newly established connection to a peer.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - The connected peer.
+    /// * `connection_id` - The id of the connection.
+    /// * `endpoint` - Who dialed whom, and at which address.
+    pub fn on_connection_established(&mut self, peer_id: PeerId, connection_id: ConnectionId, endpoint: &ConnectedPoint) {
+        self.peers.entry(peer_id).or_default().connections.insert(connection_id, TrackedConnection {
+            outbound: endpoint.is_dialer(),
+            remote_addr: endpoint.get_remote_address().clone(),
+            established: Instant::now(),
+        });
+    }
+
+    /// Records a closed connection to a peer, forgetting the peer once no connection is left.
+    pub fn on_connection_closed(&mut self, peer_id: &PeerId, connection_id: ConnectionId) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.connections.remove(&connection_id);
+            if peer.connections.is_empty() {
+                self.peers.remove(peer_id);
+            }
+        }
+    }
+
+    /// Records that a connected peer subscribed to a topic.
+    pub fn on_subscribed(&mut self, peer_id: &PeerId, topic: TopicHash) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.topics.insert(topic);
+        }
+    }
+
+    /// Records that a connected peer unsubscribed from a topic.
+    pub fn on_unsubscribed(&mut self, peer_id: &PeerId, topic: &TopicHash) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.topics.remove(topic);
+        }
+    }
+
+    /// Records a round-trip time measured by ping.
+    pub fn on_ping(&mut self, peer_id: &PeerId, rtt: Duration) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.rtt = Some(rtt);
+        }
+    }
+
+    /// Records the information a peer reported through identify.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - The identified peer.
+    /// * `agent_version` - The name and version of the peer's software.
+    /// * `listen_addrs` - The addresses the peer listens on.
+    pub fn on_identified(&mut self, peer_id: &PeerId, agent_version: String, listen_addrs: Vec<Multiaddr>) {
+        if let Some(peer) = self.peers.get_mut(peer_id) {
+            peer.agent_version = Some(agent_version);
+            peer.listen_addrs = listen_addrs;
+        }
+    }
+
+    /// Returns whether a connection to the peer is open.
+    pub fn is_connected(&self, peer_id: &PeerId) -> bool {
+        self.peers.contains_key(peer_id)
+    }
+
+    /// Returns the connected peers.
+    pub fn peers(&self) -> impl Iterator<Item = (&PeerId, &TrackedPeer)> {
+        self.peers.iter()
+    }
+}
@@ -0,0 +1,169 @@
+//! This module implements **per-peer rate limits** for each kind of message.
+//!
+//! Every peer gets a token bucket per message kind. A message takes one token, and
+//! tokens refill at a steady rate up to the bucket capacity, so a peer can send
+//! short bursts but not flood the node. Quotas can be overridden in the node
+//! configuration.
+//!
+//! Buckets outlive connections, so a peer cannot refill them by reconnecting. A
+//! bucket is only dropped once it has refilled completely, when a fresh bucket would
+//! be no different.
+
+use std::{
+    collections::HashMap,
+    time::Instant,
+};
+
+use libp2p::PeerId;
+use serde::{Serialize, Deserialize};
+
+/// The kinds of messages that are rate limited.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    /// A block announced over GossipSub.
+    NewBlock,
+
+    /// A transaction announced over GossipSub.
+    NewTransaction,
+
+    /// A finality vote announced over GossipSub.
+    Vote,
+
+    /// A `GetStatus` sync request.
+    GetStatus,
+
+    /// A `GetHeaders` sync request.
+    GetHeaders,
+
+    /// A `GetBlocks` sync request.
+    GetBlocks,
+
+    /// A `GetCompactBlock` request for an announced block.
+    GetCompactBlock,
+
+    /// A `GetTransactions` request for the transactions of a compact block.
+    GetTransactions,
+
+    /// A GossipSub message of a type the node does not know (see [`crate::wire`]).
+    UnknownMessage,
+}
+
+impl MessageKind {
+    /// Returns the default quota of the message kind.
+    ///
+    /// Sync requests make the node read and send parts of its chain, so they get
+    /// smaller quotas than the gossip messages every peer relays.
+    fn default_quota(self) -> Quota {
+        let (capacity, refill_per_sec) = match self {
+            MessageKind::NewBlock => (20.0, 2.0),
+            MessageKind::NewTransaction => (100.0, 20.0),
+            MessageKind::Vote => (100.0, 20.0),
+            MessageKind::GetStatus => (10.0, 1.0),
+            MessageKind::GetHeaders => (20.0, 2.0),
+            MessageKind::GetBlocks => (40.0, 8.0),
+            MessageKind::GetCompactBlock => (20.0, 2.0),
+            MessageKind::GetTransactions => (20.0, 2.0),
+            MessageKind::UnknownMessage => (20.0, 2.0),
+        };
+        Quota { capacity, refill_per_sec }
+    }
+}
+
+/// How many messages of a kind a peer may send.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Quota {
+    /// Maximum burst of messages.
+    pub capacity: f64,
+
+    /// Messages allowed per second in the long run.
+    pub refill_per_sec: f64,
+}
+
+impl Quota {
+    /// Checks that the quota lets some messages through.
+    ///
+    /// # Errors
+    ///
+    /// Returns the problem if the burst is below one message or the rate is not positive.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.capacity.is_nan() || self.capacity < 1.0 {
+            return Err(format!("capacity {} must be at least 1", self.capacity));
+        }
+        if self.refill_per_sec.is_nan() || self.refill_per_sec <= 0.0 {
+            return Err(format!("refill_per_sec {} must be positive", self.refill_per_sec));
+        }
+        Ok(())
+    }
+}
+
+/// The tokens left to a peer for one message kind.
+#[derive(Debug)]
+struct TokenBucket {
+    /// Tokens available.
+    tokens: f64,
+
+    /// When the tokens were last refilled.
+    refilled: Instant,
+}
+
+/// Rate limits the messages of every peer.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Quotas overridden by the node configuration.
+    quotas: HashMap<MessageKind, Quota>,
+
+    /// The bucket of each peer and message kind.
+    buckets: HashMap<(PeerId, MessageKind), TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Creates a rate limiter with the default quotas, overridden by `quotas`.
+    pub fn new(quotas: HashMap<MessageKind, Quota>) -> Self {
+        RateLimiter { quotas, buckets: HashMap::new() }
+    }
+
+    /// Takes a token from the peer's bucket for a message kind.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the message is within the peer's quota.
+    /// - `false` if the bucket is empty and the message should be dropped.
+    pub fn allow(&mut self, peer_id: PeerId, kind: MessageKind) -> bool {
+        let quota = self.quota(kind);
+        let now = Instant::now();
+        let bucket = self.buckets.entry((peer_id, kind)).or_insert(TokenBucket {
+            tokens: quota.capacity,
+            refilled: now,
+        });
+
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * quota.refill_per_sec).min(quota.capacity);
+        bucket.refilled = now;
+
+        if bucket.tokens < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Drops the buckets that have refilled completely, whether their peer is still
+    /// connected or not.
+    pub fn prune(&mut self) {
+        let now = Instant::now();
+        let quotas: HashMap<MessageKind, Quota> = self.buckets.keys()
+            .map(|(_, kind)| (*kind, self.quota(*kind)))
+            .collect();
+        self.buckets.retain(|(_, kind), bucket| {
+            let quota = quotas[kind];
+            let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+            bucket.tokens + elapsed * quota.refill_per_sec < quota.capacity
+        });
+    }
+
+    /// Returns the quota of a message kind: the configured one, or the default.
+    fn quota(&self, kind: MessageKind) -> Quota {
+        self.quotas.get(&kind).copied().unwrap_or_else(|| kind.default_quota())
+    }
+}
@@ -0,0 +1,125 @@
+//! This module defines the **chain state** derived from the transactions of the blockchain.
+//!
+//! The state tracks account balances, bonded stake and transaction nonces. It starts
+//! from the allocations of the chain spec and is updated by every appended block.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::chain_spec::{ChainSpec, ConsensusKind};
+use crate::transaction::{Transaction, TransactionKind};
+
+/// The account state of the blockchain.
+#[derive(Debug, Clone, Default)]
+pub struct ChainState {
+    /// Unbonded balance per hex-encoded public key.
+    balances: HashMap<String, u64>,
+
+    /// Bonded stake per hex-encoded public key, ordered by key for deterministic iteration.
+    stakes: BTreeMap<String, u64>,
+
+    /// Number of transactions applied per sender.
+    nonces: HashMap<String, u64>,
+}
+
+impl ChainState {
+    /// Creates the genesis state of a chain spec.
+    ///
+    /// # Arguments
+    ///
+    /// * `spec` - The chain spec holding the genesis balances and stakes.
+    pub fn genesis(spec: &ChainSpec) -> Self {
+        let balances = spec.balances.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        let stakes = match &spec.consensus {
+            ConsensusKind::ProofOfStake { genesis_stakes } => genesis_stakes.iter()
+                .filter(|(_, stake)| **stake > 0)
+                .map(|(k, v)| (k.clone(), *v))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+
+        ChainState {
+            balances,
+            stakes,
+            nonces: HashMap::new(),
+        }
+    }
+
+    /// Applies a transaction to the state.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx` - The transaction to apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the problem if the transaction is badly signed,
+    /// out of order, bonds or unbonds nothing, spends more than the sender holds or
+    /// would overflow a balance or stake. The state is left unchanged in that case.
+    pub fn apply(&mut self, tx: &Transaction) -> Result<(), String> {
+        if !tx.verify_signature() {
+            return Err(format!("Transaction {} has an invalid signature.", tx.id()));
+        }
+
+        let expected_nonce = self.nonce(&tx.sender);
+        if tx.nonce != expected_nonce {
+            return Err(format!("Transaction {} has nonce {}, expected {}.", tx.id(), tx.nonce, expected_nonce));
+        }
+
+        match &tx.kind {
+            TransactionKind::Data(_) => {}
+            TransactionKind::Bond(amount) => {
+                if *amount == 0 {
+                    return Err("Cannot bond 0.".to_string());
+                }
+                let balance = self.balance(&tx.sender);
+                if balance < *amount {
+                    return Err(format!("Cannot bond {}: balance is only {}.", amount, balance));
+                }
+                let stake = self.stake(&tx.sender).checked_add(*amount)
+                    .ok_or_else(|| format!("Cannot bond {}: the stake would overflow.", amount))?;
+                self.balances.insert(tx.sender.clone(), balance - amount);
+                self.stakes.insert(tx.sender.clone(), stake);
+            }
+            TransactionKind::Unbond(amount) => {
+                if *amount == 0 {
+                    return Err("Cannot unbond 0.".to_string());
+                }
+                let stake = self.stake(&tx.sender);
+                if stake < *amount {
+                    return Err(format!("Cannot unbond {}: stake is only {}.", amount, stake));
+                }
+                let balance = self.balance(&tx.sender).checked_add(*amount)
+                    .ok_or_else(|| format!("Cannot unbond {}: the balance would overflow.", amount))?;
+                if stake == *amount {
+                    self.stakes.remove(&tx.sender);
+                } else {
+                    self.stakes.insert(tx.sender.clone(), stake - amount);
+                }
+                self.balances.insert(tx.sender.clone(), balance);
+            }
+        }
+
+        self.nonces.insert(tx.sender.clone(), expected_nonce + 1);
+        Ok(())
+    }
+
+    /// Returns the unbonded balance of an account.
+    pub fn balance(&self, account: &str) -> u64 {
+        self.balances.get(account).copied().unwrap_or(0)
+    }
+
+    /// Returns the bonded stake of an account.
+    pub fn stake(&self, account: &str) -> u64 {
+        self.stakes.get(account).copied().unwrap_or(0)
+    }
+
+    /// Returns all non-zero stakes, ordered by public key.
+    pub fn get_stakes(&self) -> &BTreeMap<String, u64> {
+        &self.stakes
+    }
+
+    /// Returns the nonce the next transaction of `account` must carry.
+    pub fn nonce(&self, account: &str) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+}
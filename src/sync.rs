@@ -0,0 +1,1099 @@
+//! This module implements **chain synchronization** over a direct request-response protocol.
+//!
+//! Instead of flooding whole chains over GossipSub, a node asks its peers for their
+//! status (`GetStatus`), then for block headers (`GetHeaders`) and finally for the
+//! blocks it is missing by hash (`GetBlocks`). Requests and responses are
+//! CBOR-encoded and only travel between the two peers involved.
+//!
+//! Right after connecting, both peers exchange their status as a **handshake**: peers
+//! speaking a protocol version the other no longer supports or following another
+//! chain are disconnected.
+//! The status also carries the peer's clock, from which the network-adjusted time of
+//! the node is derived (see [`crate::clock`]).
+//!
+//! Sync is **headers-first**: the node downloads the header chain of the best peer
+//! and checks that it links up and is signed by allowed producers, then fetches the block bodies in
+//! windows from every peer that has them, in parallel. Failed or timed-out windows
+//! are requested again, and bodies are added to the local chain as soon as they
+//! follow on from it.
+//!
+//! New blocks are only **announced** over GossipSub, by hash and height. A node
+//! missing an announced block fetches it from the announcing peer as a **compact
+//! block** (`GetCompactBlock`), rebuilds it from its mempool and requests only the
+//! missing transactions (`GetTransactions`). If the block cannot be rebuilt, it is
+//! fetched in full with `GetBlocks`. The announcement is forwarded once the node has
+//! checked and added the block.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use libp2p::{
+    gossipsub,
+    request_response::{self, cbor, OutboundRequestId, ProtocolSupport},
+    swarm::Swarm,
+    PeerId, StreamProtocol,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::block::{Block, BlockHeader, CompactBlock};
+use crate::blockchain::Blockchain;
+use crate::chain_spec::ChainSpec;
+use crate::clock::{self, NetworkClock};
+use crate::consensus;
+use crate::crypto;
+use crate::finality::{FinalityGadget, Vote};
+use crate::inventory::SeenCache;
+use crate::mempool::Mempool;
+use crate::network::CustomBehaviour;
+use crate::peer_manager::Misbehaviour;
+use crate::rate_limit::MessageKind;
+use crate::transaction::Transaction;
+use crate::wire;
+
+/// The sync protocol name.
+const SYNC_PROTOCOL: StreamProtocol = StreamProtocol::new("/p2p_blockchain/sync/1.0.0");
+
+/// Software name and version announced to peers.
+pub const USER_AGENT: &str = concat!("p2p_blockchain/", env!("CARGO_PKG_VERSION"));
+
+/// Maximum number of headers returned for one `GetHeaders` request.
+///
+/// The first headers are requested from half this window below the local tip, so
+/// forks up to `MAX_HEADERS / 2` blocks deep can be resolved.
+const MAX_HEADERS: usize = 128;
+
+/// Maximum number of blocks returned for one `GetBlocks` request.
+const MAX_BLOCKS: usize = 128;
+
+/// Number of blocks fetched from a peer in one `GetBlocks` request during sync.
+const BODY_WINDOW: usize = 16;
+
+/// Highest chain height a peer may claim. It is far above any real chain and only keeps
+/// claims from overflowing the height arithmetic.
+const MAX_BEST_HEIGHT: usize = u32::MAX as usize;
+
+/// Number of times the status of a peer is requested before giving up. A peer that
+/// has not completed the handshake by then is disconnected.
+const MAX_STATUS_ATTEMPTS: u32 = 3;
+
+/// Time after which an unanswered sync request fails and is sent again.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The sync behaviour, exchanging [`SyncRequest`]s and [`SyncResponse`]s.
+pub type SyncBehaviour = cbor::Behaviour<SyncRequest, SyncResponse>;
+
+/// Requests sent to a single peer to synchronize with its chain.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[allow(clippy::enum_variant_names)]
+pub enum SyncRequest {
+    /// Asks for up to `count` headers, starting at height `from`.
+    GetHeaders { from: usize, count: usize },
+
+    /// Asks for the blocks with the given hashes.
+    GetBlocks { hashes: Vec<String> },
+
+    /// Asks for a summary of the peer's chain.
+    GetStatus,
+
+    /// Asks for the compact form of the block with the given hash.
+    GetCompactBlock { hash: String },
+
+    /// Asks for the transactions at the given positions in the block with the given hash.
+    GetTransactions { hash: String, indexes: Vec<usize> },
+}
+
+impl SyncRequest {
+    /// Returns the rate-limited kind of the request.
+    pub fn kind(&self) -> MessageKind {
+        match self {
+            SyncRequest::GetHeaders { .. } => MessageKind::GetHeaders,
+            SyncRequest::GetBlocks { .. } => MessageKind::GetBlocks,
+            SyncRequest::GetStatus => MessageKind::GetStatus,
+            SyncRequest::GetCompactBlock { .. } => MessageKind::GetCompactBlock,
+            SyncRequest::GetTransactions { .. } => MessageKind::GetTransactions,
+        }
+    }
+}
+
+/// Responses to [`SyncRequest`]s.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum SyncResponse {
+    /// The requested headers, in height order.
+    Headers(Vec<BlockHeader>),
+
+    /// The requested blocks the peer knows, in the order they were requested.
+    Blocks(Vec<Block>),
+
+    /// A summary of the peer's chain.
+    Status(ChainStatus),
+
+    /// The requested compact block, or `None` if the peer does not know it.
+    CompactBlock(Option<CompactBlock>),
+
+    /// The requested transactions, in the order they were requested. Empty if the
+    /// peer does not know the block.
+    Transactions(Vec<Transaction>),
+}
+
+/// A summary of a node and its chain, exchanged in the handshake.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainStatus {
+    /// The wire protocol version of the node (see [`wire::WIRE_VERSION`]).
+    pub protocol_version: u32,
+
+    /// The oldest wire protocol version the node interoperates with. Absent from the
+    /// status of nodes that predate it.
+    #[serde(default)]
+    pub min_protocol_version: u32,
+
+    /// The chain id of the node's chain spec.
+    pub chain_id: String,
+
+    /// The hash of the genesis block.
+    pub genesis_hash: String,
+
+    /// The height of the last block.
+    pub best_height: usize,
+
+    /// The hash of the last block.
+    pub best_hash: String,
+
+    /// The software name and version of the node.
+    pub user_agent: String,
+
+    /// The node's clock when it sent the status, in milliseconds since the UNIX epoch.
+    #[serde(default)]
+    pub time: u128,
+}
+
+impl ChainStatus {
+    /// Checks that a peer's status is compatible with the local chain.
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason the peer is incompatible: a protocol version one of the two
+    /// nodes no longer supports, another chain id, another genesis block or an
+    /// implausible height.
+    ///
+    /// Peers of other protocol versions are kept as long as each supports the other's
+    /// version, so nodes can be upgraded one at a time.
+    fn check_compatible(&self, local_blockchain: &Blockchain) -> Result<(), String> {
+        if self.protocol_version < wire::MIN_WIRE_VERSION {
+            return Err(format!(
+                "protocol version {} is older than the oldest supported version {}.",
+                self.protocol_version, wire::MIN_WIRE_VERSION
+            ));
+        }
+        if self.min_protocol_version > wire::WIRE_VERSION {
+            return Err(format!(
+                "peer requires protocol version {} or later (local version {}).",
+                self.min_protocol_version, wire::WIRE_VERSION
+            ));
+        }
+        if self.chain_id != local_blockchain.get_spec().chain_id {
+            return Err(format!("chain id {} differs from the local chain id.", self.chain_id));
+        }
+        if self.genesis_hash != local_blockchain.get_blocks()[0].get_hash() {
+            return Err("genesis block differs from the local genesis block.".to_string());
+        }
+        if self.best_height > MAX_BEST_HEIGHT {
+            return Err(format!("height {} is implausible.", self.best_height));
+        }
+        Ok(())
+    }
+}
+
+/// Creates the sync behaviour of the local node.
+pub fn new_sync() -> SyncBehaviour {
+    let config = request_response::Config::default().with_request_timeout(REQUEST_TIMEOUT);
+    cbor::Behaviour::new([(SYNC_PROTOCOL, ProtocolSupport::Full)], config)
+}
+
+/// What an outbound sync request asked for.
+#[derive(Debug)]
+enum PendingRequest {
+    /// A `GetStatus` request, sent for the given time to the peer.
+    Status { attempt: u32 },
+
+    /// A `GetHeaders` request to the peer the header chain is downloaded from, during
+    /// the sync run with the given generation.
+    Headers { generation: u64 },
+
+    /// A `GetBlocks` request for the bodies of a window of heights, during the sync run
+    /// with the given generation.
+    Blocks { peer: PeerId, window: Range<usize>, generation: u64 },
+
+    /// A `GetCompactBlock` request for a block announced over GossipSub.
+    Compact { hash: String, message_id: gossipsub::MessageId },
+
+    /// A `GetTransactions` request for the transactions of a compact block missing
+    /// from the mempool.
+    Missing { compact: CompactBlock, transactions: Vec<Option<Transaction>>, message_id: gossipsub::MessageId },
+
+    /// A `GetBlocks` request for a block announced over GossipSub, when its compact
+    /// form could not be rebuilt.
+    Announced { hash: String, message_id: gossipsub::MessageId },
+}
+
+/// The state of a running sync.
+#[derive(Debug)]
+struct SyncProgress {
+    /// Identifies the sync run. Responses to requests of an earlier run are dropped,
+    /// since the heights they cover may no longer match the header chain.
+    generation: u64,
+
+    /// The peer the header chain is downloaded from.
+    header_peer: PeerId,
+
+    /// The best height announced by the header peer.
+    target: usize,
+
+    /// The downloaded header chain, starting at the first block missing from the local chain.
+    headers: Vec<BlockHeader>,
+
+    /// Whether the header chain is complete and bodies are being fetched.
+    fetching_bodies: bool,
+
+    /// Windows of heights whose bodies still have to be requested.
+    windows: VecDeque<Range<usize>>,
+
+    /// Downloaded bodies not yet added to the local chain, by height.
+    bodies: BTreeMap<usize, Block>,
+
+    /// Peers that sent bodies not matching the header chain.
+    excluded: HashSet<PeerId>,
+
+    /// When the sync started.
+    started: Instant,
+
+    /// The local height when the sync started.
+    start_height: usize,
+}
+
+impl SyncProgress {
+    /// Returns the height of the first downloaded header.
+    fn base(&self) -> usize {
+        self.headers.first().map_or(0, |header| header.height)
+    }
+
+    /// Returns the downloaded headers of a window of heights.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(&[BlockHeader])` if the header chain covers the whole window.
+    /// - `None` otherwise.
+    fn window_headers(&self, window: &Range<usize>) -> Option<&[BlockHeader]> {
+        let base = self.base();
+        self.headers.get(window.start.checked_sub(base)?..window.end.checked_sub(base)?)
+    }
+}
+
+/// Synchronizes the local chain with the chains of connected peers.
+#[derive(Debug, Default)]
+pub struct Syncer {
+    /// The last status reported by each connected peer.
+    peers: HashMap<PeerId, ChainStatus>,
+
+    /// Requests waiting for a response.
+    pending: HashMap<OutboundRequestId, PendingRequest>,
+
+    /// The running sync, if the local chain is behind a peer.
+    progress: Option<SyncProgress>,
+
+    /// The generation of the last sync run started.
+    generation: u64,
+
+    /// Peers that sent invalid headers or blocks, not yet reported.
+    misbehaving: Vec<(PeerId, Misbehaviour)>,
+
+    /// Local finality votes cast for synchronized blocks, not yet broadcast.
+    votes: Vec<Vote>,
+
+    /// Hashes of announced blocks already fetched or being fetched.
+    seen: SeenCache,
+
+    /// The clock offsets of the peers that sent their status.
+    clock: NetworkClock,
+}
+
+impl Syncer {
+    /// Creates a syncer that knows no peers yet.
+    pub fn new() -> Self {
+        Syncer::default()
+    }
+
+    /// Asks a peer for its status: the handshake right after connecting, and later
+    /// to learn its new tip. A sync starts if the peer is ahead.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The peer to ask.
+    pub fn request_status(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId) {
+        self.send_status_request(swarm, peer_id, 1);
+    }
+
+    /// Sends the `attempt`-th status request to a peer.
+    fn send_status_request(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId, attempt: u32) {
+        let request_id = swarm.behaviour_mut().sync.send_request(&peer_id, SyncRequest::GetStatus);
+        self.pending.insert(request_id, PendingRequest::Status { attempt });
+    }
+
+    /// Fetches a block announced over GossipSub from the announcing peer, unless it
+    /// was already fetched.
+    ///
+    /// The announcement is validated, and so forwarded or not, once the block arrives.
+    ///
+    /// # Arguments
+    ///
+    /// * `swarm` - The network swarm instance.
+    /// * `peer_id` - The peer that relayed the announcement.
+    /// * `message_id` - The GossipSub id of the announcement.
+    /// * `hash` - The hash of the announced block.
+    ///
+    /// # Returns
+    ///
+    /// - `true` if the block is being fetched.
+    /// - `false` if the hash was already seen.
+    pub fn fetch_announced(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId, message_id: gossipsub::MessageId, hash: String) -> bool {
+        if !self.seen.insert(&hash) {
+            return false;
+        }
+        let request = SyncRequest::GetCompactBlock { hash: hash.clone() };
+        let request_id = swarm.behaviour_mut().sync.send_request(&peer_id, request);
+        self.pending.insert(request_id, PendingRequest::Compact { hash, message_id });
+        true
+    }
+
+    /// Fetches an announced block in full, when its compact form could not be rebuilt.
+    fn fetch_full(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer_id: PeerId, hash: String, message_id: gossipsub::MessageId) {
+        println!("Fetching the full block {} from {}.", hash, peer_id);
+        let request = SyncRequest::GetBlocks { hashes: vec![hash.clone()] };
+        let request_id = swarm.behaviour_mut().sync.send_request(&peer_id, request);
+        self.pending.insert(request_id, PendingRequest::Announced { hash, message_id });
+    }
+
+    /// Rebuilds an announced block from its compact form and the mempool, requesting
+    /// the transactions the mempool lacks.
+    ///
+    /// # Returns
+    ///
+    /// The rebuilt block, if the mempool held all of its transactions.
+    fn on_compact_block(
+        &mut self,
+        peer: PeerId,
+        hash: String,
+        message_id: gossipsub::MessageId,
+        compact: Option<CompactBlock>,
+        swarm: &mut Swarm<CustomBehaviour>,
+        mempool: &Mempool,
+    ) -> Option<Block> {
+        let Some(compact) = compact.filter(|compact| compact.header.hash == hash) else {
+            self.fetch_full(swarm, peer, hash, message_id);
+            return None;
+        };
+
+        let transactions = compact.match_transactions(mempool.transactions());
+        let indexes: Vec<usize> = transactions.iter()
+            .enumerate()
+            .filter(|(_, tx)| tx.is_none())
+            .map(|(index, _)| index)
+            .collect();
+        if indexes.is_empty() {
+            return self.rebuild(peer, message_id, compact, transactions.into_iter().flatten().collect(), swarm);
+        }
+
+        println!("Requesting {} of {} transactions of block {} from {}.", indexes.len(), transactions.len(), compact.header.height, peer);
+        let request = SyncRequest::GetTransactions { hash, indexes };
+        let request_id = swarm.behaviour_mut().sync.send_request(&peer, request);
+        self.pending.insert(request_id, PendingRequest::Missing { compact, transactions, message_id });
+        None
+    }
+
+    /// Fills the transactions missing from a compact block and rebuilds it.
+    ///
+    /// # Returns
+    ///
+    /// The rebuilt block, unless a full fetch was needed.
+    fn on_missing_transactions(
+        &mut self,
+        peer: PeerId,
+        compact: CompactBlock,
+        mut transactions: Vec<Option<Transaction>>,
+        message_id: gossipsub::MessageId,
+        received: Vec<Transaction>,
+        swarm: &mut Swarm<CustomBehaviour>,
+    ) -> Option<Block> {
+        if transactions.iter().filter(|tx| tx.is_none()).count() != received.len() {
+            self.fetch_full(swarm, peer, compact.header.hash, message_id);
+            return None;
+        }
+
+        let mut received = received.into_iter();
+        for tx in transactions.iter_mut().filter(|tx| tx.is_none()) {
+            *tx = received.next();
+        }
+        self.rebuild(peer, message_id, compact, transactions.into_iter().flatten().collect(), swarm)
+    }
+
+    /// Rebuilds a block from its compact form and all of its transactions, falling back
+    /// to a full fetch if they do not hash to the announced block.
+    fn rebuild(
+        &mut self,
+        peer: PeerId,
+        message_id: gossipsub::MessageId,
+        compact: CompactBlock,
+        transactions: Vec<Transaction>,
+        swarm: &mut Swarm<CustomBehaviour>,
+    ) -> Option<Block> {
+        let hash = compact.header.hash.clone();
+        let block = compact.into_block(transactions);
+        if block.is_none() {
+            println!("Failed to rebuild block {} from its compact form.", hash);
+            self.fetch_full(swarm, peer, hash, message_id);
+        }
+        block
+    }
+
+    /// Returns the status a peer reported in its handshake, if it completed it.
+    pub fn peer_status(&self, peer_id: &PeerId) -> Option<&ChainStatus> {
+        self.peers.get(peer_id)
+    }
+
+    /// Returns the peers that sent invalid headers or blocks since the last call, with their misbehaviour.
+    pub fn take_misbehaving(&mut self) -> Vec<(PeerId, Misbehaviour)> {
+        std::mem::take(&mut self.misbehaving)
+    }
+
+    /// Returns the local votes cast for blocks added since the last call.
+    pub fn take_votes(&mut self) -> Vec<Vote> {
+        std::mem::take(&mut self.votes)
+    }
+
+    /// Forgets the status and clock of a disconnected peer.
+    ///
+    /// Its requests in flight fail on their own and are sent again to other peers.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - The disconnected peer.
+    /// * `local_blockchain` - The local blockchain, whose network-adjusted time is updated.
+    pub fn on_disconnected(&mut self, peer_id: &PeerId, local_blockchain: &mut Blockchain) {
+        self.peers.remove(peer_id);
+        self.clock.forget(peer_id);
+        local_blockchain.set_time_offset(self.clock.offset());
+    }
+
+    /// Prints the progress of the sync: local height, target height and download rate.
+    ///
+    /// # Arguments
+    ///
+    /// * `local_blockchain` - The local blockchain instance.
+    pub fn print_status(&self, local_blockchain: &Blockchain) {
+        let height = local_blockchain.get_blocks().len() - 1;
+        println!("Network time offset: {}ms from the local clock ({} peer clocks).", self.clock.offset(), self.clock.samples());
+        let Some(progress) = &self.progress else {
+            println!("Synced at height {} ({} peers).", height, self.peers.len());
+            return;
+        };
+
+        let elapsed = progress.started.elapsed().as_secs_f64();
+        let rate = height.saturating_sub(progress.start_height) as f64 / elapsed.max(1.0);
+        let phase = if progress.fetching_bodies { "downloading blocks" } else { "downloading headers" };
+        let in_flight = self.pending.values()
+            .filter(|request| matches!(request, PendingRequest::Blocks { generation, .. } if *generation == progress.generation))
+            .count();
+
+        println!("Syncing: {}", phase);
+        println!("Height: {}", height);
+        println!("Target: {}", progress.target);
+        println!("Rate: {:.1} blocks/s", rate);
+        println!("Headers: {}, bodies downloaded: {}, windows in flight: {}", progress.headers.len(), progress.bodies.len(), in_flight);
+    }
+
+    /// Handles events of the sync protocol: answers requests and follows up on responses.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The request-response event.
+    /// * `swarm` - The network swarm instance.
+    /// * `local_blockchain` - The local blockchain instance.
+    /// * `mempool` - The local pool of pending transactions.
+    /// * `finality` - The finality gadget tracking validator votes.
+    pub fn handle_event(
+        &mut self,
+        event: request_response::Event<SyncRequest, SyncResponse>,
+        swarm: &mut Swarm<CustomBehaviour>,
+        local_blockchain: &mut Blockchain,
+        mempool: &mut Mempool,
+        finality: &mut FinalityGadget,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message, .. } => match message {
+                request_response::Message::Request { request, channel, .. } => {
+                    let response = answer(request, local_blockchain);
+                    if swarm.behaviour_mut().sync.send_response(channel, response).is_err() {
+                        println!("Failed to answer sync request from {}: connection closed.", peer);
+                    }
+                }
+                request_response::Message::Response { request_id, response } => {
+                    let Some(request) = self.pending.remove(&request_id) else {
+                        return;
+                    };
+                    match (request, response) {
+                        (PendingRequest::Status { .. }, SyncResponse::Status(status)) => {
+                            self.on_status(peer, status, swarm, local_blockchain);
+                        }
+                        (PendingRequest::Headers { generation }, SyncResponse::Headers(headers)) => {
+                            if self.is_current(generation) {
+                                self.on_headers(peer, headers, swarm, local_blockchain);
+                            }
+                        }
+                        (PendingRequest::Blocks { window, generation, .. }, SyncResponse::Blocks(blocks)) => {
+                            if self.is_current(generation) {
+                                self.on_blocks(peer, window, blocks);
+                                self.apply_bodies(swarm, local_blockchain, mempool, finality);
+                            }
+                        }
+                        (PendingRequest::Compact { hash, message_id }, SyncResponse::CompactBlock(compact)) => {
+                            if let Some(block) = self.on_compact_block(peer, hash, message_id.clone(), compact, swarm, mempool) {
+                                let hash = block.hash.clone();
+                                let acceptance = self.on_announced_block(peer, &hash, vec![block], swarm, local_blockchain, mempool, finality);
+                                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &peer, acceptance);
+                            }
+                        }
+                        (PendingRequest::Missing { compact, transactions, message_id }, SyncResponse::Transactions(received)) => {
+                            if let Some(block) = self.on_missing_transactions(peer, compact, transactions, message_id.clone(), received, swarm) {
+                                let hash = block.hash.clone();
+                                let acceptance = self.on_announced_block(peer, &hash, vec![block], swarm, local_blockchain, mempool, finality);
+                                swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &peer, acceptance);
+                            }
+                        }
+                        (PendingRequest::Announced { hash, message_id }, SyncResponse::Blocks(blocks)) => {
+                            let acceptance = self.on_announced_block(peer, &hash, blocks, swarm, local_blockchain, mempool, finality);
+                            swarm.behaviour_mut().gossipsub.report_message_validation_result(&message_id, &peer, acceptance);
+                        }
+                        // The request got no usable answer: treat it as failed, and the peer as misbehaving.
+                        (request, _) => {
+                            println!("Peer {} answered a sync request with the wrong response.", peer);
+                            self.misbehaving.push((peer, Misbehaviour::InvalidSyncResponse));
+                            match request {
+                                PendingRequest::Compact { hash, message_id } | PendingRequest::Announced { hash, message_id } => {
+                                    self.ignore_announcement(swarm, peer, &hash, &message_id);
+                                }
+                                PendingRequest::Missing { compact, message_id, .. } => {
+                                    self.ignore_announcement(swarm, peer, &compact.header.hash, &message_id);
+                                }
+                                request => self.retry(peer, request, swarm, local_blockchain),
+                            }
+                        }
+                    }
+                    self.request_bodies(swarm);
+                }
+            },
+            request_response::Event::OutboundFailure { peer, request_id, error, .. } => {
+                println!("Sync request to {} failed: {}", peer, error);
+                match self.pending.remove(&request_id) {
+                    Some(request @ (PendingRequest::Status { .. } | PendingRequest::Blocks { .. } | PendingRequest::Headers { .. })) => {
+                        self.retry(peer, request, swarm, local_blockchain);
+                    }
+                    // Fall back to fetching the whole block.
+                    Some(PendingRequest::Compact { hash, message_id }) => {
+                        self.fetch_full(swarm, peer, hash, message_id);
+                    }
+                    Some(PendingRequest::Missing { compact, message_id, .. }) => {
+                        self.fetch_full(swarm, peer, compact.header.hash, message_id);
+                    }
+                    Some(PendingRequest::Announced { hash, message_id }) => {
+                        self.ignore_announcement(swarm, peer, &hash, &message_id);
+                    }
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Hands the work of a failed sync request to other peers.
+    ///
+    /// A status request is sent again, up to [`MAX_STATUS_ATTEMPTS`] times, after which a
+    /// peer that never completed the handshake is disconnected. A window of bodies goes
+    /// to the next idle peer, and a header download restarts from the best peer still
+    /// connected. Requests of an earlier sync run are dropped.
+    fn retry(&mut self, peer: PeerId, request: PendingRequest, swarm: &mut Swarm<CustomBehaviour>, local_blockchain: &Blockchain) {
+        match request {
+            PendingRequest::Status { attempt } if swarm.is_connected(&peer) => {
+                if attempt < MAX_STATUS_ATTEMPTS {
+                    self.send_status_request(swarm, peer, attempt + 1);
+                } else if !self.peers.contains_key(&peer) {
+                    println!("Disconnecting from {}: it did not complete the handshake.", peer);
+                    let _ = swarm.disconnect_peer_id(peer);
+                }
+            }
+            PendingRequest::Blocks { window, generation, .. } => {
+                if let Some(progress) = self.progress.as_mut().filter(|progress| progress.generation == generation) {
+                    progress.windows.push_front(window);
+                }
+                self.request_bodies(swarm);
+            }
+            PendingRequest::Headers { generation } if self.is_current(generation) => {
+                self.peers.remove(&peer);
+                self.progress = None;
+                self.start(swarm, local_blockchain);
+            }
+            _ => {}
+        }
+    }
+
+    /// Gives up on a block announcement whose block could not be fetched: the
+    /// announcement is not forwarded, and another announcement of the block triggers a
+    /// new download.
+    fn ignore_announcement(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer: PeerId, hash: &str, message_id: &gossipsub::MessageId) {
+        self.seen.remove(hash);
+        swarm.behaviour_mut().gossipsub.report_message_validation_result(message_id, &peer, gossipsub::MessageAcceptance::Ignore);
+    }
+
+    /// Checks a fetched announced block and adds it to the local chain if it extends the tip.
+    ///
+    /// # Returns
+    ///
+    /// The validation result of the announcement:
+    /// - `Accept` if the block was added, so the announcement is forwarded.
+    /// - `Reject` if the block is invalid.
+    /// - `Ignore` if the peer did not send the block or it does not extend the local tip.
+    #[allow(clippy::too_many_arguments)]
+    fn on_announced_block(
+        &mut self,
+        peer: PeerId,
+        hash: &str,
+        blocks: Vec<Block>,
+        swarm: &mut Swarm<CustomBehaviour>,
+        local_blockchain: &mut Blockchain,
+        mempool: &mut Mempool,
+        finality: &mut FinalityGadget,
+    ) -> gossipsub::MessageAcceptance {
+        let Some(block) = blocks.into_iter().next().filter(|block| block.hash == hash) else {
+            println!("Peer {} did not send the block {} it announced.", peer, hash);
+            self.seen.remove(hash);
+            return gossipsub::MessageAcceptance::Ignore;
+        };
+
+        // Attribute the block to its producer before accepting it.
+        let producer = match block.producer.as_deref().and_then(crypto::peer_id_of) {
+            Some(producer) if block.verify_signature() => producer,
+            _ => {
+                println!("Block rejected: missing or invalid producer signature (announced by {}).", peer);
+                self.misbehaving.push((peer, Misbehaviour::InvalidBlock));
+                return gossipsub::MessageAcceptance::Reject;
+            }
+        };
+        println!("Block {} produced by {}", block.get_height(), producer);
+
+        // The local chain moved on, or the peer is on a longer fork: sync with it.
+        let tip_hash = local_blockchain.get_last_block().map(|tip| tip.get_hash());
+        if tip_hash.as_deref() != Some(block.prev_block_hash.as_str()) {
+            self.request_status(swarm, peer);
+            return gossipsub::MessageAcceptance::Ignore;
+        }
+
+        let height = block.get_height();
+        if !local_blockchain.add_block(block) {
+            println!("Block {} from {} breaks the chain rules.", height, peer);
+            self.misbehaving.push((peer, Misbehaviour::InvalidBlock));
+            return gossipsub::MessageAcceptance::Reject;
+        }
+        println!("Successfully added the block to local blockchain!");
+        self.votes.extend(adopt(height, local_blockchain, mempool, finality));
+        gossipsub::MessageAcceptance::Accept
+    }
+
+    /// Records a peer's status and clock, and starts a sync if the peer is ahead.
+    ///
+    /// An incompatible peer is disconnected.
+    fn on_status(&mut self, peer: PeerId, status: ChainStatus, swarm: &mut Swarm<CustomBehaviour>, local_blockchain: &mut Blockchain) {
+        if let Err(reason) = status.check_compatible(local_blockchain) {
+            println!("Disconnecting from {} ({}): {}", peer, status.user_agent, reason);
+            self.peers.remove(&peer);
+            let _ = swarm.disconnect_peer_id(peer);
+            return;
+        }
+        if !self.peers.contains_key(&peer) {
+            println!("Handshake with {}: {} at height {}.", peer, status.user_agent, status.best_height);
+        }
+        self.clock.on_peer_time(peer, status.time);
+        local_blockchain.set_time_offset(self.clock.offset());
+        self.peers.insert(peer, status);
+        if self.progress.is_none() {
+            self.start(swarm, local_blockchain);
+        }
+    }
+
+    /// Starts downloading the header chain of the best peer, if it is ahead of the local chain.
+    fn start(&mut self, swarm: &mut Swarm<CustomBehaviour>, local_blockchain: &Blockchain) {
+        let local_height = local_blockchain.get_blocks().len() - 1;
+        let Some((&peer, status)) = self.peers.iter().max_by_key(|(_, status)| status.best_height) else {
+            return;
+        };
+        if status.best_height <= local_height {
+            return;
+        }
+
+        println!("Syncing with {} from height {} to {}.", peer, local_height, status.best_height);
+        self.generation += 1;
+        self.progress = Some(SyncProgress {
+            generation: self.generation,
+            header_peer: peer,
+            target: status.best_height,
+            headers: Vec::new(),
+            fetching_bodies: false,
+            windows: VecDeque::new(),
+            bodies: BTreeMap::new(),
+            excluded: HashSet::new(),
+            started: Instant::now(),
+            start_height: local_height,
+        });
+
+        // Start below the local tip, so a fork of the last blocks is detected.
+        let from = local_height.saturating_sub(MAX_HEADERS / 2).max(local_blockchain.finalized_height());
+        self.request_headers(swarm, peer, from);
+    }
+
+    /// Requests the next batch of headers from the header peer.
+    fn request_headers(&mut self, swarm: &mut Swarm<CustomBehaviour>, peer: PeerId, from: usize) {
+        let request = SyncRequest::GetHeaders { from, count: MAX_HEADERS };
+        let request_id = swarm.behaviour_mut().sync.send_request(&peer, request);
+        self.pending.insert(request_id, PendingRequest::Headers { generation: self.generation });
+    }
+
+    /// Returns whether a request belongs to the running sync, rather than to one that
+    /// was aborted or completed since it was sent.
+    fn is_current(&self, generation: u64) -> bool {
+        self.progress.as_ref().is_some_and(|progress| progress.generation == generation)
+    }
+
+    /// Checks a batch of headers and appends it to the header chain.
+    ///
+    /// Every header must be signed by a producer allowed by the chain spec and link to
+    /// the previous one. Headers of blocks the local chain already has are skipped, and
+    /// headers beyond the height the peer claimed when the sync started are dropped.
+    /// Once the header chain reaches the target, it is split into windows whose bodies
+    /// are then fetched.
+    fn on_headers(&mut self, peer: PeerId, mut headers: Vec<BlockHeader>, swarm: &mut Swarm<CustomBehaviour>, local_blockchain: &Blockchain) {
+        let Some(progress) = &mut self.progress else {
+            return;
+        };
+        let blocks = local_blockchain.get_blocks();
+
+        if headers.len() > MAX_HEADERS {
+            println!("Sync with {} aborted: it sent {} headers, more than requested.", peer, headers.len());
+            self.misbehaving.push((peer, Misbehaviour::InvalidSyncResponse));
+            self.progress = None;
+            return;
+        }
+        let target = progress.target;
+        headers.retain(|header| header.height <= target);
+
+        for header in &headers {
+            let linked = match progress.headers.last() {
+                Some(last) => header.height == last.height + 1 && header.prev_block_hash == last.hash,
+                None => {
+                    if blocks.get(header.height).is_some_and(|block| block.hash == header.hash) {
+                        continue;
+                    }
+                    header.height > 0 && blocks.get(header.height - 1).is_some_and(|block| block.hash == header.prev_block_hash)
+                }
+            };
+            if !linked && !progress.headers.is_empty() {
+                println!("Sync with {} aborted: header {} does not link to the previous header.", peer, header.height);
+                self.misbehaving.push((peer, Misbehaviour::InvalidSyncResponse));
+                self.progress = None;
+                return;
+            }
+            if !linked {
+                println!("Sync with {} aborted: header {} does not link to the chain.", peer, header.height);
+                self.progress = None;
+                return;
+            }
+            if let Err(reason) = check_header(header, local_blockchain.get_spec()) {
+                println!("Sync with {} aborted: {}", peer, reason);
+                self.misbehaving.push((peer, Misbehaviour::InvalidSyncResponse));
+                self.progress = None;
+                return;
+            }
+            progress.headers.push(header.clone());
+        }
+
+        let last_height = headers.last().map(|header| header.height);
+        match last_height {
+            Some(last_height) if headers.len() == MAX_HEADERS && last_height < progress.target => {
+                self.request_headers(swarm, peer, last_height + 1);
+            }
+            _ => {
+                // The peer claimed a longer chain than it has.
+                let end = progress.base() + progress.headers.len();
+                if end <= blocks.len() {
+                    println!("Sync with {} stopped: its chain is not longer than the local chain.", peer);
+                    self.abort_invalid_chain();
+                    return;
+                }
+
+                println!("Downloaded {} headers up to height {}.", progress.headers.len(), end - 1);
+                progress.fetching_bodies = true;
+                let mut start = progress.base();
+                while start < end {
+                    progress.windows.push_back(start..(start + BODY_WINDOW).min(end));
+                    start += BODY_WINDOW;
+                }
+            }
+        }
+    }
+
+    /// Hands the next windows of bodies to the peers that are not busy.
+    fn request_bodies(&mut self, swarm: &mut Swarm<CustomBehaviour>) {
+        let Some(progress) = &mut self.progress else {
+            return;
+        };
+        if !progress.fetching_bodies {
+            return;
+        }
+
+        // Peers still answering a window of an earlier run are busy as well.
+        let busy: HashSet<PeerId> = self.pending.values()
+            .filter_map(|request| match request {
+                PendingRequest::Blocks { peer, .. } => Some(*peer),
+                _ => None,
+            })
+            .collect();
+        let mut idle: Vec<PeerId> = self.peers.iter()
+            .filter(|(peer, _)| !busy.contains(peer) && !progress.excluded.contains(peer))
+            .map(|(peer, _)| *peer)
+            .collect();
+
+        while let Some(window) = progress.windows.pop_front() {
+            // Only peers that announced a chain reaching the window can serve it.
+            let Some(position) = idle.iter().position(|peer| self.peers[peer].best_height >= window.end - 1) else {
+                progress.windows.push_front(window);
+                break;
+            };
+            let Some(headers) = progress.window_headers(&window) else {
+                println!("Dropped window {:?}: it is not covered by the header chain.", window);
+                continue;
+            };
+            let hashes = headers.iter().map(|header| header.hash.clone()).collect();
+            let peer = idle.swap_remove(position);
+            let request_id = swarm.behaviour_mut().sync.send_request(&peer, SyncRequest::GetBlocks { hashes });
+            self.pending.insert(request_id, PendingRequest::Blocks { peer, window, generation: progress.generation });
+        }
+
+        if !progress.windows.is_empty() && busy.is_empty() && idle.is_empty() && self.peers.keys().all(|peer| progress.excluded.contains(peer)) {
+            println!("Sync aborted: no peer can serve the missing blocks.");
+            self.progress = None;
+        }
+    }
+
+    /// Stores the bodies of a window, if they match the header chain.
+    ///
+    /// Each body must hash to its header's hash and carry the producer's signature, so
+    /// a peer cannot pass off tampered transactions under a valid header. A peer sending
+    /// bad bodies is penalized and no longer asked, and the window is requested again
+    /// from another peer.
+    fn on_blocks(&mut self, peer: PeerId, window: Range<usize>, blocks: Vec<Block>) {
+        let Some(progress) = &mut self.progress else {
+            return;
+        };
+
+        let matches = blocks.len() == window.len()
+            && progress.window_headers(&window).is_some_and(|headers| {
+                blocks.iter().zip(headers).all(|(block, header)| {
+                    block.hash == header.hash
+                        && block.get_height() == header.height
+                        && block.calculate_hash() == block.hash
+                        && block.verify_signature()
+                })
+            });
+        if !matches {
+            println!("Peer {} sent blocks not matching the header chain.", peer);
+            progress.excluded.insert(peer);
+            self.misbehaving.push((peer, Misbehaviour::InvalidSyncResponse));
+            progress.windows.push_front(window);
+            return;
+        }
+
+        for block in blocks {
+            progress.bodies.insert(block.get_height(), block);
+        }
+    }
+
+    /// Aborts the sync because the chain of the header peer turned out to be invalid or
+    /// shorter than it claimed.
+    ///
+    /// The peer is penalized and its status forgotten, so the next sync does not pick it
+    /// again until it reports a new status.
+    fn abort_invalid_chain(&mut self) {
+        if let Some(progress) = self.progress.take() {
+            self.misbehaving.push((progress.header_peer, Misbehaviour::InvalidSyncResponse));
+            self.peers.remove(&progress.header_peer);
+        }
+    }
+
+    /// Adds the downloaded bodies that follow on from the local chain.
+    ///
+    /// Bodies extending the local tip are added one by one. Bodies of a fork are held
+    /// back until they form a longer chain than the local one, which then replaces it;
+    /// the transactions of the replaced blocks go back to the mempool if still valid.
+    fn apply_bodies(
+        &mut self,
+        swarm: &mut Swarm<CustomBehaviour>,
+        local_blockchain: &mut Blockchain,
+        mempool: &mut Mempool,
+        finality: &mut FinalityGadget,
+    ) {
+        let Some(progress) = &mut self.progress else {
+            return;
+        };
+        let base = progress.base();
+        let old_len = local_blockchain.get_blocks().len();
+
+        let on_fork = local_blockchain.get_blocks().iter().skip(base).zip(&progress.headers)
+            .any(|(block, header)| block.hash != header.hash);
+
+        let (first_changed, orphaned) = if on_fork {
+            // Switch to the fork once the bodies downloaded from its start outgrow the local chain.
+            let contiguous = (base..).take_while(|height| progress.bodies.contains_key(height)).count();
+            if base + contiguous <= old_len {
+                return;
+            }
+            let mut candidate = local_blockchain.get_blocks()[..base].to_vec();
+            candidate.extend((base..base + contiguous).filter_map(|height| progress.bodies.get(&height).cloned()));
+            let orphaned = local_blockchain.get_blocks()[base..].to_vec();
+            if !local_blockchain.replace_chain(candidate) {
+                println!("Sync aborted: the downloaded chain is invalid.");
+                self.abort_invalid_chain();
+                return;
+            }
+            (base, orphaned)
+        } else {
+            while let Some(block) = progress.bodies.get(&local_blockchain.get_blocks().len()).cloned() {
+                let height = block.get_height();
+                if !local_blockchain.add_block(block) {
+                    println!("Sync aborted: invalid block at height {}.", height);
+                    self.abort_invalid_chain();
+                    return;
+                }
+            }
+            (old_len, Vec::new())
+        };
+
+        let new_len = local_blockchain.get_blocks().len();
+        progress.bodies.retain(|height, _| *height >= new_len);
+        self.votes.extend(adopt(first_changed, local_blockchain, mempool, finality));
+
+        // Transactions of the replaced blocks that the new chain lacks are pending again.
+        let restored = mempool.restore(&orphaned, local_blockchain.get_state());
+        if restored > 0 {
+            println!("Put {} transactions of orphaned blocks back into the mempool.", restored);
+        }
+
+        if new_len > progress.target {
+            println!("Sync complete at height {} ({:.1}s).", new_len - 1, progress.started.elapsed().as_secs_f64());
+            let header_peer = progress.header_peer;
+            self.progress = None;
+
+            // The peer may have produced more blocks in the meantime.
+            self.request_status(swarm, header_peer);
+        }
+    }
+}
+
+/// Checks that a header is signed by the producer it names, and that the producer
+/// may produce the block as far as the header alone tells.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the signature is missing or invalid, or
+/// the producer breaks the consensus rules of the chain spec.
+fn check_header(header: &BlockHeader, spec: &ChainSpec) -> Result<(), String> {
+    match (&header.producer, &header.signature) {
+        (Some(producer), Some(signature)) if crypto::verify(producer, header.hash.as_bytes(), signature) => {}
+        _ => return Err(format!("header {} has a missing or invalid signature.", header.height)),
+    }
+    consensus::validate_header_producer(spec, header).map_err(|reason| format!("header {}: {}", header.height, reason))
+}
+
+/// Updates the mempool and the finality gadget after blocks from `first_changed` on were added.
+///
+/// # Returns
+///
+/// The local votes to broadcast for the added blocks.
+fn adopt(
+    first_changed: usize,
+    local_blockchain: &mut Blockchain,
+    mempool: &mut Mempool,
+    finality: &mut FinalityGadget,
+) -> Vec<Vote> {
+    let new_len = local_blockchain.get_blocks().len();
+    for block in &local_blockchain.get_blocks()[first_changed..] {
+        mempool.remove_included(block, local_blockchain.get_state());
+    }
+
+    let mut votes = Vec::new();
+    for height in first_changed..new_len {
+        votes.extend(finality.on_block_added(local_blockchain, height));
+    }
+    votes
+}
+
+/// Builds the response to a sync request from the local blockchain.
+///
+/// # Arguments
+///
+/// * `request` - The received request.
+/// * `local_blockchain` - The local blockchain instance.
+fn answer(request: SyncRequest, local_blockchain: &Blockchain) -> SyncResponse {
+    let blocks = local_blockchain.get_blocks();
+    match request {
+        SyncRequest::GetStatus => {
+            let best = local_blockchain.get_last_block().expect("Blockchain has a genesis block");
+            SyncResponse::Status(ChainStatus {
+                protocol_version: wire::WIRE_VERSION,
+                min_protocol_version: wire::MIN_WIRE_VERSION,
+                chain_id: local_blockchain.get_spec().chain_id.clone(),
+                genesis_hash: blocks[0].get_hash(),
+                best_height: best.get_height(),
+                best_hash: best.get_hash(),
+                user_agent: USER_AGENT.to_string(),
+                time: clock::local_time(),
+            })
+        }
+        SyncRequest::GetHeaders { from, count } => SyncResponse::Headers(
+            blocks.iter().skip(from).take(count.min(MAX_HEADERS)).map(Block::header).collect(),
+        ),
+        SyncRequest::GetBlocks { hashes } => SyncResponse::Blocks(
+            hashes.iter()
+                .take(MAX_BLOCKS)
+                .filter_map(|hash| blocks.iter().find(|block| block.hash == *hash))
+                .cloned()
+                .collect(),
+        ),
+        SyncRequest::GetCompactBlock { hash } => SyncResponse::CompactBlock(
+            blocks.iter().rev().find(|block| block.hash == hash).map(Block::compact),
+        ),
+        // Each transaction is sent at most once, however often the request repeats its index.
+        SyncRequest::GetTransactions { hash, indexes } => SyncResponse::Transactions(
+            blocks.iter().rev()
+                .find(|block| block.hash == hash)
+                .map(|block| {
+                    let mut sent = HashSet::new();
+                    indexes.iter()
+                        .filter(|index| **index < block.transactions.len() && sent.insert(**index))
+                        .take(block.transactions.len())
+                        .map(|index| block.transactions[*index].clone())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        ),
+    }
+}
@@ -0,0 +1,92 @@
+//! This module defines the **transactions** carried by blocks.
+//!
+//! Every transaction is signed by its sender and numbered with a per-sender nonce,
+//! so it cannot be forged or replayed. Transactions either carry arbitrary data or
+//! change the sender's stake in the chain state.
+
+use sha2::{Sha256, Digest};
+use serde::{Serialize, Deserialize};
+use libp2p::identity;
+
+use crate::crypto;
+
+/// The action performed by a transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum TransactionKind {
+    /// Stores arbitrary data on the chain.
+    Data(String),
+
+    /// Moves `amount` from the sender's balance into its stake.
+    Bond(u64),
+
+    /// Moves `amount` from the sender's stake back into its balance.
+    Unbond(u64),
+}
+
+/// A signed transaction.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Transaction {
+    /// The hex-encoded Ed25519 public key of the sender.
+    pub sender: String,
+
+    /// The sender's transaction counter, starting at `0`.
+    pub nonce: u64,
+
+    /// The action performed by the transaction.
+    pub kind: TransactionKind,
+
+    /// The sender's hex-encoded signature over the transaction id.
+    pub signature: String,
+}
+
+impl Transaction {
+    /// Creates a transaction signed by `keypair`.
+    ///
+    /// # Arguments
+    ///
+    /// * `keypair` - The sender's Ed25519 keypair.
+    /// * `nonce` - The sender's next nonce.
+    /// * `kind` - The action to perform.
+    ///
+    /// # Returns
+    ///
+    /// - `Some(Transaction)` carrying a valid signature.
+    /// - `None` if the keypair is not an Ed25519 keypair.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// let keypair = identity::Keypair::generate_ed25519();
+    /// let tx = Transaction::new_signed(&keypair, 0, TransactionKind::Bond(10)).unwrap();
+    /// assert!(tx.verify_signature());
+    /// ```
+    pub fn new_signed(keypair: &identity::Keypair, nonce: u64, kind: TransactionKind) -> Option<Transaction> {
+        let sender = crypto::encode_public_key(keypair)?;
+        let mut tx = Transaction {
+            sender,
+            nonce,
+            kind,
+            signature: String::new(),
+        };
+        tx.signature = crypto::sign(keypair, tx.id().as_bytes())?;
+        Some(tx)
+    }
+
+    /// Computes the SHA-256 id of the transaction over its sender, nonce and kind.
+    ///
+    /// # Returns
+    ///
+    /// A `String` containing the hex-encoded id.
+    pub fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.sender);
+        hasher.update(self.nonce.to_string());
+        hasher.update(serde_json::to_string(&self.kind).expect("Failed to serialize transaction"));
+        format!("{:x}", hasher.finalize())
+    }
+
+    /// Verifies the sender's signature over the transaction id.
+    pub fn verify_signature(&self) -> bool {
+        crypto::verify(&self.sender, self.id().as_bytes(), &self.signature)
+    }
+}
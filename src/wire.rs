@@ -0,0 +1,193 @@
+//! This module defines the **wire format** of the messages gossiped between nodes.
+//!
+//! Every message travels in an envelope carrying the wire version of the sender, a
+//! type tag and the payload of the message:
+//!
+//! ```json
+//! { "version": 1, "type": "new_block", "payload": { "hash": "...", "height": 42 } }
+//! ```
+//!
+//! The envelope lets nodes of different versions interoperate during a rolling upgrade:
+//! - A message of an unknown type is ignored without penalty: it was most likely sent
+//!   by a newer node. It is not forwarded either, since the node cannot validate it.
+//! - A message of a known type is decoded whatever the sender's version. Payload
+//!   fields the node does not know are skipped, so newer nodes may add fields as long
+//!   as older nodes can do without them.
+//! - A message of a known type whose payload does not decode is malformed and rejected.
+
+use std::{fmt, sync::OnceLock};
+
+use serde::{de::{self, value::MapDeserializer}, Serialize, Deserialize};
+
+use crate::network::NetworkMessage;
+
+/// The wire version of this node, sent in every envelope and in the sync handshake.
+/// It increases when message types or payload fields are added, so peers can tell
+/// which messages a node understands.
+pub const WIRE_VERSION: u32 = 1;
+
+/// The oldest wire version this node still interoperates with. It is only raised by a
+/// change older nodes cannot follow, e.g. a sync request they would misread.
+pub const MIN_WIRE_VERSION: u32 = 1;
+
+/// A gossip message in its envelope.
+#[derive(Serialize, Deserialize, Debug)]
+struct Envelope {
+    /// The wire version of the sender.
+    version: u32,
+
+    /// The type tag and payload of the message.
+    #[serde(flatten)]
+    message: NetworkMessage,
+}
+
+/// The fields of an envelope every version agrees on, read before the payload.
+#[derive(Deserialize, Debug)]
+struct EnvelopeHeader {
+    /// The wire version of the sender.
+    version: u32,
+
+    /// The type tag of the message.
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// A decoded gossip message.
+#[derive(Debug)]
+pub enum Decoded {
+    /// A message of a type this node understands.
+    Known(NetworkMessage),
+
+    /// A message of a type this node does not know, e.g. from a newer node.
+    Unknown {
+        /// The wire version of the sender.
+        version: u32,
+
+        /// The type tag of the message.
+        kind: String,
+    },
+}
+
+/// Encodes a message in its envelope.
+///
+/// # Returns
+///
+/// The JSON bytes published over GossipSub.
+pub fn encode(message: &NetworkMessage) -> Vec<u8> {
+    let envelope = Envelope {
+        version: WIRE_VERSION,
+        message: message.clone(),
+    };
+    serde_json::to_vec(&envelope).expect("Failed to serialize message")
+}
+
+/// Decodes a message from its envelope.
+///
+/// # Errors
+///
+/// Returns the reason the data is malformed: not an envelope, or a known type with
+/// a payload that does not decode.
+pub fn decode(data: &[u8]) -> Result<Decoded, String> {
+    let header: EnvelopeHeader = serde_json::from_slice(data).map_err(|e| format!("invalid envelope: {}", e))?;
+    if !known_types().contains(&header.kind.as_str()) {
+        return Ok(Decoded::Unknown { version: header.version, kind: header.kind });
+    }
+
+    let envelope: Envelope = serde_json::from_slice(data)
+        .map_err(|e| format!("invalid {} payload: {}", header.kind, e))?;
+    Ok(Decoded::Known(envelope.message))
+}
+
+/// Returns the type tags of the messages this node understands: the tags of the
+/// [`NetworkMessage`] variants.
+///
+/// Serde does not list the variants of an enum, but reports them when asked to
+/// deserialize an unknown one. The tags are read from that error once, so they
+/// always follow the enum.
+fn known_types() -> &'static [&'static str] {
+    static KNOWN_TYPES: OnceLock<&'static [&'static str]> = OnceLock::new();
+    KNOWN_TYPES.get_or_init(|| {
+        let probe = MapDeserializer::<_, VariantsError>::new([("type", "")].into_iter());
+        match NetworkMessage::deserialize(probe) {
+            Err(VariantsError(Some(variants))) => variants,
+            _ => panic!("Failed to list the NetworkMessage variants"),
+        }
+    })
+}
+
+/// The error of a deserialization probing for the variant names of an enum.
+#[derive(Debug)]
+struct VariantsError(Option<&'static [&'static str]>);
+
+impl de::Error for VariantsError {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        VariantsError(None)
+    }
+
+    fn unknown_variant(_variant: &str, expected: &'static [&'static str]) -> Self {
+        VariantsError(Some(expected))
+    }
+}
+
+impl fmt::Display for VariantsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "variants: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for VariantsError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_types_follow_the_message_enum() {
+        let messages = [
+            NetworkMessage::NewBlock { hash: "abc".to_string(), height: 1 },
+            NetworkMessage::NewTransaction("{}".to_string()),
+            NetworkMessage::Vote("{}".to_string()),
+        ];
+        assert_eq!(known_types().len(), messages.len());
+        for message in &messages {
+            let header: EnvelopeHeader = serde_json::from_slice(&encode(message)).unwrap();
+            assert!(known_types().contains(&header.kind.as_str()), "{} is not known", header.kind);
+        }
+    }
+
+    #[test]
+    fn decodes_a_known_type_from_a_newer_version() {
+        let data = br#"{"version": 7, "type": "new_block", "payload": {"hash": "abc", "height": 3, "extra": true}}"#;
+        match decode(data) {
+            Ok(Decoded::Known(NetworkMessage::NewBlock { hash, height })) => {
+                assert_eq!(hash, "abc");
+                assert_eq!(height, 3);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_an_unknown_type_from_a_newer_version() {
+        let data = br#"{"version": 2, "type": "new_receipt", "payload": {"id": 1}}"#;
+        match decode(data) {
+            Ok(Decoded::Unknown { version, kind }) => {
+                assert_eq!(version, 2);
+                assert_eq!(kind, "new_receipt");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_a_known_type_with_an_invalid_payload() {
+        let data = br#"{"version": 1, "type": "new_block", "payload": {"hash": 42}}"#;
+        assert!(decode(data).is_err());
+    }
+
+    #[test]
+    fn rejects_data_that_is_not_an_envelope() {
+        assert!(decode(b"not json").is_err());
+        assert!(decode(br#"{"type": "vote", "payload": "{}"}"#).is_err());
+    }
+}